@@ -0,0 +1,2891 @@
+use crate::middleware::MiddlewareChain;
+use crate::one_of;
+use crate::parsers::{duration, float, header, longest_of, parse_trimmed, uint, Parser};
+use crate::stats::{self, LevelHistogram, Stats};
+use crate::template::{self, RequestCtx};
+use crate::ticker::{AlignedInterval, JitterInterval};
+use anyhow::Context;
+use reqwest::header::{HeaderName, HeaderValue};
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::{interval_at, Instant, Interval, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  the run completed
+  1  reserved for failed SLO/error-rate gates
+  2  invalid arguments or configuration
+  3  target unreachable: no request received a response
+  4  no requests were sent, e.g. because of --count 0";
+
+#[derive(clap::Parser, serde::Serialize)]
+#[command(after_help = EXIT_CODES_HELP)]
+pub struct Args {
+    /// URL of the service to barrage
+    pub addr: String,
+
+    /// JSON payload/template to barrage `addr` with, or "@path" to read it from a file. Input
+    /// that isn't JSON is sent as a string. Not sent for GET and HEAD requests.
+    /// `{{uuid}}`, `{{timestamp}}`, `{{counter}}` and `{{random_int:MIN-MAX}}` in string values
+    /// are filled in for every request
+    #[arg(short, long, value_parser = parse_data, required_unless_present_any = ["data_file", "replay"])]
+    pub data: Option<serde_json::Value>,
+
+    /// Send the contents of this file as the body instead of `data`
+    #[arg(long, conflicts_with = "data")]
+    pub data_file: Option<PathBuf>,
+
+    /// Stream `data_file` from disk on every request instead of reading it into memory once
+    #[arg(long, requires = "data_file", conflicts_with = "data")]
+    pub stream_body: bool,
+
+    /// HTTP method to send requests with (GET, POST, PUT, PATCH, DELETE, HEAD)
+    #[arg(short, long, default_value = "POST", value_parser = parse_method)]
+    #[serde(serialize_with = "serialize_method")]
+    pub method: reqwest::Method,
+
+    /// Header to add to every request, as "Key: Value". Can be repeated
+    #[arg(short = 'H', long = "header", value_parser = parse_header)]
+    #[serde(serialize_with = "serialize_headers")]
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// How often to send requests to `addr` (Ex. "500ms", "1m30s", "100rps")
+    #[arg(long, value_parser = parse_pacing, required_unless_present_any = ["rate", "replay"])]
+    pub every: Option<Pacing>,
+
+    /// Requests per second to send `addr`, instead of `every`. May be fractional, e.g. "0.5".
+    /// Same as `--every <RATE>rps`
+    #[arg(long, value_parser = parse_rate, conflicts_with = "every")]
+    pub rate: Option<Pacing>,
+
+    /// Randomize each gap between requests by up to this fraction of the time between them,
+    /// in 0.0..=1.0
+    #[arg(long, default_value = "0.0", value_parser = parse_jitter)]
+    pub jitter: f64,
+
+    /// Send on wall-clock multiples of the time between requests, e.g. at the top of every second for "1s",
+    /// instead of relative to when the run started. All workers send on the same boundaries
+    #[arg(long, conflicts_with_all = ["jitter", "replay"])]
+    pub align: bool,
+
+    /// Total number of requests to send before exiting. Runs until cancelled if unset
+    #[arg(short = 'n', long)]
+    pub count: Option<u64>,
+
+    /// Number of workers sending requests, each on its own `every` schedule
+    #[arg(short = 'c', long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    pub concurrency: u64,
+
+    /// Hard ceiling on requests per second across all workers, regardless of `every`
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..=1_000_000_000))]
+    pub max_rps: Option<u64>,
+
+    /// Delay every request by this long before sending it, to simulate a slow network
+    #[arg(long, value_parser = parse_duration)]
+    #[serde(serialize_with = "serialize_optional_duration")]
+    pub simulate_latency: Option<Duration>,
+
+    /// Read response bodies no faster than this, as "<size>/s" (Ex. "64kb/s"), to simulate a
+    /// slow network
+    #[arg(long, value_parser = parse_bandwidth)]
+    pub simulate_bandwidth: Option<u64>,
+
+    /// Write every request sent to this file, one JSON object per line, for `--replay`
+    #[arg(long, conflicts_with_all = ["stream_body", "replay"])]
+    pub record: Option<PathBuf>,
+
+    /// Send the requests from a `--record` file to `addr` instead, with the same order, timing,
+    /// headers and bodies
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+
+    /// Count requests identical to one already sent, in method, URL, headers and body, e.g. from
+    /// a `{{random_int}}` range that's too small for `count`
+    #[arg(long, conflicts_with_all = ["stream_body", "replay"])]
+    pub detect_duplicates: bool,
+
+    /// Don't send the duplicates that `--detect-duplicates` finds. They still count towards
+    /// `count`
+    #[arg(long, requires = "detect_duplicates")]
+    pub skip_duplicates: bool,
+
+    /// Estimate latency percentiles to within ~1% in fixed memory, instead of keeping every
+    /// latency to compute them exactly. For long or high-rate runs
+    #[arg(long)]
+    pub approx_percentiles: bool,
+
+    /// Stay silent on success, printing the summary only when exiting with a non-zero code
+    #[arg(long)]
+    pub summary_only_on_failure: bool,
+
+    /// Format of the end-of-run summary. "json" prints only the summary to stdout, everything
+    /// else goes to stderr
+    #[arg(long, value_enum, default_value = "text")]
+    pub output: OutputFormat,
+
+    /// Print the resolved configuration as JSON and exit without sending anything
+    #[arg(long)]
+    #[serde(skip)]
+    pub print_config: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    /// Fixed time between requests
+    Period(Duration),
+    /// Requests per second, which may be fractional
+    Rate(f64),
+}
+
+impl Pacing {
+    fn period(self) -> Duration {
+        match self {
+            Pacing::Period(period) => period,
+            Pacing::Rate(rate) => period_from_rate(rate),
+        }
+    }
+}
+
+impl std::fmt::Display for Pacing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pacing::Period(period) => write!(f, "{}", format_duration(*period)),
+            Pacing::Rate(rate) => write!(f, "{}rps", rate),
+        }
+    }
+}
+
+impl Args {
+    /// Time between requests, from `every` or `rate`. `None` when replaying.
+    fn period(&self) -> Option<Duration> {
+        self.every.or(self.rate).map(Pacing::period)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl serde::Serialize for Pacing {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+fn rate<'input>() -> impl Parser<'input, f64> {
+    float().then(one_of!("rps", "hz")).map(|(rate, _)| rate)
+}
+
+fn pacing<'input>() -> impl Parser<'input, Pacing> {
+    // the grammars overlap on prefixes ("4hz" starts with the duration "4h"), so prefer
+    // whichever consumes more
+    longest_of(vec![
+        duration().map(Pacing::Period).boxed(),
+        rate().map(Pacing::Rate).boxed(),
+    ])
+}
+
+/// A bare number of requests per second, for `--rate`.
+fn parse_rate(s: &str) -> Result<Pacing, anyhow::Error> {
+    let rate = parse_trimmed(float(), s)
+        .context(r#"expected a rate in requests per second (Ex. "200")"#)?;
+
+    check_rate(rate)?;
+    Ok(Pacing::Rate(rate))
+}
+
+fn parse_pacing(s: &str) -> Result<Pacing, anyhow::Error> {
+    let pacing = parse_trimmed(pacing(), s)
+        .context(r#"expected a duration (Ex. "500ms") or a rate (Ex. "100rps")"#)?;
+
+    match pacing {
+        Pacing::Period(period) => {
+            anyhow::ensure!(!period.is_zero(), "duration must be greater than zero")
+        }
+        Pacing::Rate(rate) => check_rate(rate)?,
+    }
+
+    Ok(pacing)
+}
+
+/// Checks that `period_from_rate` can turn `rate` into a period.
+fn check_rate(rate: f64) -> Result<(), anyhow::Error> {
+    anyhow::ensure!(rate > 0.0, "rate must be greater than zero");
+    anyhow::ensure!(
+        rate <= 1_000_000_000.0,
+        "rate cannot exceed one request per nanosecond"
+    );
+    anyhow::ensure!(1_000_000_000.0 / rate <= u64::MAX as f64, "rate is too low");
+    Ok(())
+}
+
+fn parse_data(s: &str) -> Result<serde_json::Value, anyhow::Error> {
+    let Some(path) = s.strip_prefix('@') else {
+        return Ok(serde_json::from_str(s).unwrap_or_else(|_| s.into()));
+    };
+
+    let file = std::fs::File::open(path).with_context(|| format!("failed to open {}", path))?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| format!("{} is not valid JSON", path))
+}
+
+fn parse_duration(s: &str) -> Result<Duration, anyhow::Error> {
+    let duration = parse_trimmed(duration(), s).context(r#"expected a duration (Ex. "500ms")"#)?;
+
+    anyhow::ensure!(!duration.is_zero(), "duration must be greater than zero");
+    Ok(duration)
+}
+
+/// Bytes per second, e.g. "64kb/s". Sizes are decimal, so "1kb" is 1000 bytes.
+fn bandwidth<'input>() -> impl Parser<'input, u64> {
+    uint()
+        .then(one_of! {
+            "b" => 1,
+            "kb" => 1_000,
+            "mb" => 1_000_000,
+            "gb" => 1_000_000_000,
+        })
+        .then("/s")
+        .filter_map(|((amount, per_unit), _)| amount.checked_mul(per_unit))
+}
+
+fn parse_bandwidth(s: &str) -> Result<u64, anyhow::Error> {
+    let bandwidth =
+        parse_trimmed(bandwidth(), s).context(r#"expected a bandwidth (Ex. "64kb/s")"#)?;
+
+    anyhow::ensure!(bandwidth > 0, "bandwidth must be greater than zero");
+    Ok(bandwidth)
+}
+
+fn parse_jitter(s: &str) -> Result<f64, anyhow::Error> {
+    let jitter = parse_trimmed(float(), s)
+        .context(r#"expected a fraction between 0.0 and 1.0 (Ex. "0.1")"#)?;
+
+    anyhow::ensure!(jitter <= 1.0, "jitter must be between 0.0 and 1.0");
+    Ok(jitter)
+}
+
+/// Converts a rate to the period between requests. `rate` must pass `check_rate`.
+fn period_from_rate(rate: f64) -> Duration {
+    const NANOS_PER_SEC: f64 = 1_000_000_000.0;
+
+    // round to the nearest nanosecond so rates like 3rps don't drift short
+    Duration::from_nanos((NANOS_PER_SEC / rate).round() as u64)
+}
+
+fn method<'input>() -> impl Parser<'input, reqwest::Method> {
+    one_of! {
+        "GET" => reqwest::Method::GET,
+        "POST" => reqwest::Method::POST,
+        "PUT" => reqwest::Method::PUT,
+        "PATCH" => reqwest::Method::PATCH,
+        "DELETE" => reqwest::Method::DELETE,
+        "HEAD" => reqwest::Method::HEAD,
+    }
+}
+
+fn parse_method(s: &str) -> Result<reqwest::Method, anyhow::Error> {
+    parse_trimmed(method(), s).context("expected one of GET, POST, PUT, PATCH, DELETE, HEAD")
+}
+
+fn parse_header(s: &str) -> Result<(HeaderName, HeaderValue), anyhow::Error> {
+    let (name, value) =
+        parse_trimmed(header(), s).context(r#"expected a header in the form "Key: Value""#)?;
+
+    // the value runs to the end of the input, so `parse_trimmed` can't strip its trailing end
+    let name = HeaderName::try_from(name).context("invalid header name")?;
+    let value = HeaderValue::try_from(value.trim_end()).context("invalid header value")?;
+
+    Ok((name, value))
+}
+
+fn serialize_headers<S>(
+    headers: &[(HeaderName, HeaderValue)],
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_seq(
+        headers.iter().map(|(name, value)| {
+            format!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()))
+        }),
+    )
+}
+
+fn serialize_method<S>(method: &reqwest::Method, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(method.as_str())
+}
+
+fn serialize_optional_duration<S>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match duration {
+        Some(duration) => serializer.collect_str(&format_duration(*duration)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+
+    const SECOND: u128 = 1_000_000_000;
+
+    [
+        ("d", 24 * 60 * 60 * SECOND),
+        ("h", 60 * 60 * SECOND),
+        ("m", 60 * SECOND),
+        ("s", SECOND),
+        ("ms", 1_000_000),
+        ("us", 1_000),
+    ]
+    .into_iter()
+    .find(|(_, per_unit)| nanos.is_multiple_of(*per_unit))
+    .map(|(unit, per_unit)| format!("{}{}", nanos / per_unit, unit))
+    .unwrap_or_else(|| format!("{}ns", nanos))
+}
+
+fn cap_period(every: Duration, max_rps: Option<u64>, workers: u64) -> Duration {
+    let Some(max_rps) = max_rps else {
+        return every;
+    };
+
+    // each worker runs its own schedule, so they share the cap between them
+    let min_period = u32::try_from(workers).map_or(Duration::MAX, |workers| {
+        period_from_rate(max_rps as f64).saturating_mul(workers)
+    });
+
+    if every < min_period {
+        eprintln!("{}", max_rps_warning(every, workers, max_rps, min_period));
+        min_period
+    } else {
+        every
+    }
+}
+
+/// Worded in terms of the time between requests, as it may have come from `--every` or `--rate`.
+fn max_rps_warning(every: Duration, workers: u64, max_rps: u64, capped: Duration) -> String {
+    format!(
+        "warning: sending every {:?} from each of {} workers would exceed --max-rps {}, \
+         sending every {:?} instead",
+        every, workers, max_rps, capped
+    )
+}
+
+/// Delay before `worker` first fires, spreading workers evenly over one period so they
+/// don't all send in lockstep.
+fn stagger(period: Duration, worker: u64, workers: u64) -> Duration {
+    period.mul_f64(worker as f64 / workers as f64)
+}
+
+/// Request body, resolved once before the run starts.
+enum Payload {
+    Json(serde_json::Value),
+    Bytes(bytes::Bytes),
+    /// Reopened and streamed from disk for every request, so it's never held in memory whole
+    Stream(PathBuf),
+}
+
+impl Payload {
+    fn load(args: &Args) -> anyhow::Result<Self> {
+        match (&args.data, &args.data_file) {
+            (_, Some(path)) if args.stream_body => Ok(Payload::Stream(path.clone())),
+            (_, Some(path)) => std::fs::read(path)
+                .map(|contents| Payload::Bytes(contents.into()))
+                .with_context(|| format!("failed to read --data-file {}", path.display())),
+            (Some(data), None) => Ok(Payload::Json(data.clone())),
+            (None, None) => anyhow::bail!("one of --data or --data-file is required"),
+        }
+    }
+}
+
+fn stream_file(path: PathBuf) -> reqwest::Body {
+    use futures::TryStreamExt;
+
+    let chunks = futures::stream::once(tokio::fs::File::open(path))
+        .map_ok(tokio_util::io::ReaderStream::new)
+        .try_flatten();
+
+    reqwest::Body::wrap_stream(chunks)
+}
+
+/// GET and HEAD requests are sent without a body, so a payload given for them is dropped.
+fn unsent_payload_warning(args: &Args) -> Option<String> {
+    if !matches!(args.method, reqwest::Method::GET | reqwest::Method::HEAD) {
+        return None;
+    }
+
+    let flag = match (&args.data, &args.data_file) {
+        (_, Some(_)) if args.stream_body => "--data-file and --stream-body",
+        (_, Some(_)) => "--data-file",
+        (Some(_), None) => "--data",
+        (None, None) => return None,
+    };
+    Some(format!(
+        "warning: {} requests have no body, so {} won't be sent",
+        args.method, flag
+    ))
+}
+
+fn request(
+    client: &reqwest::Client,
+    args: &Args,
+    payload: &Payload,
+    ctx: &RequestCtx,
+) -> reqwest::RequestBuilder {
+    let mut request = client.request(args.method.clone(), &args.addr);
+    for (name, value) in &args.headers {
+        request = request.header(name, value);
+    }
+
+    if matches!(args.method, reqwest::Method::GET | reqwest::Method::HEAD) {
+        return request;
+    }
+
+    match payload {
+        Payload::Json(data) if template::has_placeholders(data) => {
+            request.json(&template::render(data, ctx))
+        }
+        Payload::Json(data) => request.json(data),
+        Payload::Bytes(contents) => request.body(contents.clone()),
+        Payload::Stream(path) => request.body(stream_file(path.clone())),
+    }
+}
+
+/// One line of a `--record` file.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct RecordedRequest {
+    /// Nanoseconds since the start of the run
+    offset: u64,
+    method: String,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+}
+
+impl RecordedRequest {
+    fn capture(request: &reqwest::Request, offset: Duration) -> anyhow::Result<Self> {
+        let headers = request
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let value = value.to_str().context("header value is not UTF-8")?;
+                Ok((name.to_string(), value.to_string()))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let body = match request.body() {
+            Some(body) => {
+                let bytes = body
+                    .as_bytes()
+                    .context("streamed bodies can't be recorded")?;
+                let body = std::str::from_utf8(bytes).context("body is not UTF-8")?;
+                Some(body.to_string())
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            offset: u64::try_from(offset.as_nanos()).unwrap_or(u64::MAX),
+            method: request.method().to_string(),
+            headers,
+            body,
+        })
+    }
+
+    fn request(
+        &self,
+        client: &reqwest::Client,
+        addr: &str,
+    ) -> anyhow::Result<reqwest::RequestBuilder> {
+        let method = reqwest::Method::from_bytes(self.method.as_bytes())
+            .with_context(|| format!("invalid method `{}`", self.method))?;
+
+        let mut request = client.request(method, addr);
+        for (name, value) in &self.headers {
+            let name = HeaderName::try_from(name).context("invalid header name")?;
+            let value = HeaderValue::try_from(value).context("invalid header value")?;
+            request = request.header(name, value);
+        }
+
+        Ok(match &self.body {
+            Some(body) => request.body(body.clone()),
+            None => request,
+        })
+    }
+}
+
+fn load_recording(path: &std::path::Path) -> anyhow::Result<Vec<RecordedRequest>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read --replay {}", path.display()))?;
+
+    let mut recording = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("invalid recorded request on line {}", index + 1))
+        })
+        .collect::<anyhow::Result<Vec<RecordedRequest>>>()?;
+
+    // workers append concurrently, so lines can be slightly out of order
+    recording.sort_by_key(|recorded| recorded.offset);
+    Ok(recording)
+}
+
+/// Fingerprints of every request built, for `--detect-duplicates`. Keeps 8 bytes per distinct
+/// request for the whole run.
+#[derive(Debug, Default)]
+struct Duplicates {
+    seen: std::sync::Mutex<std::collections::HashSet<u64>>,
+    count: AtomicU64,
+}
+
+impl Duplicates {
+    /// Whether an identical request was already seen, counting it if so. Requests that can't be
+    /// fingerprinted, such as ones with streamed bodies, are never duplicates.
+    fn check(&self, request: &reqwest::RequestBuilder) -> bool {
+        let Some(request) = request.try_clone().and_then(|request| request.build().ok()) else {
+            return false;
+        };
+        let Some(fingerprint) = fingerprint(&request) else {
+            return false;
+        };
+
+        let mut seen = self.seen.lock().expect("duplicates lock poisoned");
+        let duplicate = !seen.insert(fingerprint);
+        if duplicate {
+            self.count.fetch_add(1, Ordering::Relaxed);
+        }
+        duplicate
+    }
+}
+
+fn fingerprint(request: &reqwest::Request) -> Option<u64> {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    request.method().hash(&mut hasher);
+    request.url().as_str().hash(&mut hasher);
+    for (name, value) in request.headers() {
+        name.hash(&mut hasher);
+        value.as_bytes().hash(&mut hasher);
+    }
+    let body = match request.body() {
+        // streamed bodies can't be read without sending them
+        Some(body) => Some(body.as_bytes()?),
+        None => None,
+    };
+    body.hash(&mut hasher);
+
+    Some(hasher.finish())
+}
+
+type Recorder = std::sync::Mutex<std::io::BufWriter<std::fs::File>>;
+
+fn record(recorder: &Recorder, request: &reqwest::RequestBuilder, offset: Duration) {
+    let line = request
+        .try_clone()
+        .context("streamed bodies can't be recorded")
+        .and_then(|request| request.build().context("invalid request"))
+        .and_then(|request| RecordedRequest::capture(&request, offset))
+        .and_then(|recorded| serde_json::to_string(&recorded).context("failed to serialize"));
+
+    let written = line.and_then(|line| {
+        let mut recorder = recorder.lock().expect("recorder lock poisoned");
+        writeln!(recorder, "{}", line).context("failed to write --record file")
+    });
+    if let Err(err) = written {
+        eprintln!("error: couldn't record request: {:#}", err);
+    }
+}
+
+/// Artificially degraded network, for seeing how a target behaves behind slow clients.
+#[derive(Debug, Clone, Copy, Default)]
+struct NetworkConditions {
+    latency: Option<Duration>,
+    /// Bytes per second
+    bandwidth: Option<u64>,
+}
+
+impl NetworkConditions {
+    async fn send(
+        &self,
+        client: &reqwest::Client,
+        request: reqwest::Request,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        if let Some(latency) = self.latency {
+            tokio::time::sleep(latency).await;
+        }
+
+        client.execute(request).await
+    }
+
+    /// Reads the whole body, pausing after each chunk until the average transfer rate is back
+    /// under `bandwidth` if there is one. Returns the number of bytes read.
+    async fn receive(&self, mut response: reqwest::Response) -> Result<u64, reqwest::Error> {
+        let started = Instant::now();
+        let mut received = 0;
+
+        while let Some(chunk) = response.chunk().await? {
+            received += chunk.len() as u64;
+
+            if let Some(bandwidth) = self.bandwidth {
+                let due = started + Duration::from_secs_f64(received as f64 / bandwidth as f64);
+                tokio::time::sleep_until(due).await;
+            }
+        }
+
+        Ok(received)
+    }
+}
+
+/// How a run ended, mapped onto the exit codes listed in `EXIT_CODES_HELP`.
+///
+/// Nothing maps to 1. It's the code a Rust program exits with when `main` returns an error, such
+/// as an `anyhow::Error`, so a 1 can't be told apart from an unexpected failure. It stays free for
+/// the planned SLO/error-rate gates. Panics exit with 101 and don't collide with any code here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    Success,
+    ConfigError,
+    TargetUnreachable,
+    NoRequestsSent,
+}
+
+impl ExitReason {
+    pub fn for_config_error(err: &clap::Error) -> Self {
+        // --help and --version are reported through clap errors too, but aren't failures
+        if err.use_stderr() {
+            ExitReason::ConfigError
+        } else {
+            ExitReason::Success
+        }
+    }
+
+    fn for_run(dispatched: u64, outcomes: &Outcomes) -> Self {
+        let responses = outcomes.responses.load(Ordering::Relaxed);
+        let connect_errors = outcomes.connect_errors.load(Ordering::Relaxed);
+
+        if dispatched == 0 {
+            ExitReason::NoRequestsSent
+        } else if responses == 0 && connect_errors > 0 {
+            ExitReason::TargetUnreachable
+        } else {
+            ExitReason::Success
+        }
+    }
+}
+
+impl From<ExitReason> for ExitCode {
+    fn from(reason: ExitReason) -> Self {
+        match reason {
+            ExitReason::Success => ExitCode::SUCCESS,
+            // 1 is skipped on purpose, see `ExitReason`
+            ExitReason::ConfigError => ExitCode::from(2),
+            ExitReason::TargetUnreachable => ExitCode::from(3),
+            ExitReason::NoRequestsSent => ExitCode::from(4),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Outcomes {
+    responses: AtomicU64,
+    connect_errors: AtomicU64,
+    errors: ErrorCounts,
+}
+
+impl Outcomes {
+    fn record(&self, result: &Result<reqwest::Response, reqwest::Error>) {
+        match result {
+            Ok(_) => {
+                self.responses.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(err) => {
+                if err.is_connect() {
+                    self.connect_errors.fetch_add(1, Ordering::Relaxed);
+                }
+                self.errors.record(&error_reason(err));
+            }
+        }
+    }
+}
+
+/// Why a request failed, without the URL, so the same failure on different requests is counted
+/// together.
+fn error_reason(err: &reqwest::Error) -> String {
+    if err.is_timeout() {
+        return "timed out".to_string();
+    }
+
+    // reqwest's own message names the URL; the innermost cause is what actually went wrong
+    let mut reason = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(cause) = source {
+        reason = cause.to_string();
+        source = cause.source();
+    }
+
+    reason
+}
+
+/// How many distinct error reasons are kept; any more are counted under `OTHER_ERRORS`.
+const MAX_DISTINCT_ERRORS: usize = 32;
+const OTHER_ERRORS: &str = "other errors";
+/// How many error reasons the summary lists.
+const TOP_ERRORS: usize = 5;
+
+#[derive(Debug, Default)]
+struct ErrorCounts {
+    counts: std::sync::Mutex<std::collections::HashMap<String, u64>>,
+}
+
+impl ErrorCounts {
+    fn record(&self, reason: &str) {
+        let mut counts = self.counts.lock().expect("error counts lock poisoned");
+        if let Some(count) = counts.get_mut(reason) {
+            *count += 1;
+            return;
+        }
+
+        let reason = if counts.len() < MAX_DISTINCT_ERRORS {
+            reason
+        } else {
+            OTHER_ERRORS
+        };
+        *counts.entry(reason.to_string()).or_default() += 1;
+    }
+
+    /// The `n` most common reasons, most common first.
+    fn top(&self, n: usize) -> Vec<(String, u64)> {
+        let counts = self.counts.lock().expect("error counts lock poisoned");
+        let mut top: Vec<_> = counts
+            .iter()
+            .map(|(reason, count)| (reason.clone(), *count))
+            .collect();
+        top.sort_by(|(a_reason, a), (b_reason, b)| b.cmp(a).then_with(|| a_reason.cmp(b_reason)));
+        top.truncate(n);
+        top
+    }
+}
+
+struct Shared {
+    client: reqwest::Client,
+    args: Args,
+    started: Instant,
+    recorder: Option<Recorder>,
+    /// Applied to every request just before it's sent, after it was recorded
+    middleware: MiddlewareChain,
+    duplicates: Option<Duplicates>,
+    outcomes: Outcomes,
+    stats: Stats,
+    dispatched: AtomicU64,
+    /// Requests actually sent, which leaves out duplicates skipped by `--skip-duplicates`
+    sent: AtomicU64,
+    in_flight: AtomicU64,
+    /// Feeds `{{counter}}`, so it's unique across workers
+    counter: AtomicU64,
+    /// Cancelled once the last of `count` requests is claimed
+    count_reached: CancellationToken,
+    network: NetworkConditions,
+}
+
+/// Claims one of the `count` request slots, shared by all workers so they can't collectively
+/// overshoot, returning its index. Always succeeds when there is no `count`.
+fn claim(dispatched: &AtomicU64, count: Option<u64>) -> Option<u64> {
+    dispatched
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sent| match count {
+            Some(count) if sent >= count => None,
+            _ => Some(sent + 1),
+        })
+        .ok()
+}
+
+/// A worker's send schedule: a fixed `Interval`, a `JitterInterval` once `--jitter` is set, or
+/// an `AlignedInterval` for `--align`.
+enum Ticker {
+    Fixed(Interval),
+    Aligned(AlignedInterval),
+    Jittered {
+        first_tick: Instant,
+        period: Duration,
+        factor: f64,
+        // started on the first tick, so staggered workers don't all jitter from the run's start
+        interval: Option<JitterInterval>,
+    },
+}
+
+impl Ticker {
+    fn new(first_tick: Instant, period: Duration, jitter: f64) -> Self {
+        if jitter == 0.0 {
+            return Ticker::Fixed(interval_at(first_tick, period));
+        }
+
+        Ticker::Jittered {
+            first_tick,
+            period,
+            factor: jitter,
+            interval: None,
+        }
+    }
+
+    async fn tick(&mut self) {
+        match self {
+            Ticker::Fixed(interval) => {
+                interval.tick().await;
+            }
+            Ticker::Aligned(interval) => {
+                interval.tick().await;
+            }
+            Ticker::Jittered {
+                interval: Some(interval),
+                ..
+            } => {
+                interval.tick().await;
+            }
+            Ticker::Jittered {
+                first_tick,
+                period,
+                factor,
+                interval,
+            } => {
+                tokio::time::sleep_until(*first_tick).await;
+                *interval = Some(JitterInterval::new(*period, *factor));
+            }
+        }
+    }
+}
+
+async fn worker(shared: Arc<Shared>, payload: Arc<Payload>, mut ticker: Ticker) {
+    let mut in_flight = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shared.count_reached.cancelled() => break,
+        }
+        while in_flight.try_join_next().is_some() {}
+
+        let Some(index) = claim(&shared.dispatched, shared.args.count) else {
+            break;
+        };
+        // don't leave the other workers waiting out a period for a slot that isn't there
+        let last = shared.args.count == Some(index + 1);
+        if last {
+            shared.count_reached.cancel();
+        }
+
+        let ctx = RequestCtx::new(shared.counter.fetch_add(1, Ordering::Relaxed));
+        let request = request(&shared.client, &shared.args, &payload, &ctx);
+        let skip = shared
+            .duplicates
+            .as_ref()
+            .is_some_and(|duplicates| duplicates.check(&request) && shared.args.skip_duplicates);
+        if !skip {
+            if let Some(recorder) = &shared.recorder {
+                record(recorder, &request, shared.started.elapsed());
+            }
+            dispatch(&shared, request, &mut in_flight);
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    in_flight.join_all().await;
+}
+
+/// Sends a recording's requests at the same offsets from the start of the run as they were
+/// originally sent at.
+async fn replay(shared: Arc<Shared>, recording: Vec<RecordedRequest>) {
+    let mut in_flight = JoinSet::new();
+
+    for recorded in recording {
+        tokio::time::sleep_until(shared.started + Duration::from_nanos(recorded.offset)).await;
+        while in_flight.try_join_next().is_some() {}
+
+        match recorded.request(&shared.client, &shared.args.addr) {
+            Ok(request) => {
+                shared.dispatched.fetch_add(1, Ordering::Relaxed);
+                dispatch(&shared, request, &mut in_flight);
+            }
+            Err(err) => eprintln!("error: skipping recorded request: {:#}", err),
+        }
+    }
+
+    in_flight.join_all().await;
+}
+
+fn dispatch(shared: &Arc<Shared>, request: reqwest::RequestBuilder, in_flight: &mut JoinSet<()>) {
+    let shared_for_request = Arc::clone(shared);
+    shared.sent.fetch_add(1, Ordering::Relaxed);
+    shared.in_flight.fetch_add(1, Ordering::Relaxed);
+    in_flight.spawn(async move {
+        let network = shared_for_request.network;
+        let request = match request.build() {
+            Ok(request) => Ok(shared_for_request.middleware.apply(request).await),
+            Err(err) => Err(err),
+        };
+        let sent = Instant::now();
+        let result = match request {
+            Ok(request) => network.send(&shared_for_request.client, request).await,
+            Err(err) => Err(err),
+        };
+        shared_for_request.outcomes.record(&result);
+
+        match result {
+            Ok(response) => {
+                // always read the body, so the connection can be reused and latency covers it
+                let status = response.status().as_u16();
+                match network.receive(response).await {
+                    Ok(_) => shared_for_request.stats.record(sent.elapsed(), status),
+                    // a cut-off body is a failed request, whatever the status said
+                    Err(err) => {
+                        shared_for_request
+                            .outcomes
+                            .errors
+                            .record(&format!("reading response: {}", error_reason(&err)));
+                        shared_for_request.stats.record_failure();
+                    }
+                }
+            }
+            // counted by reason in `outcomes`, for the summary rather than per request
+            Err(_) => shared_for_request.stats.record_failure(),
+        }
+        shared_for_request.in_flight.fetch_sub(1, Ordering::Relaxed);
+    });
+}
+
+fn summary(dispatched: u64, elapsed: Duration) -> String {
+    if dispatched == 0 {
+        return "0 requests sent — check configuration".to_string();
+    }
+
+    format!(
+        "ran for {:.2?}: dispatched {} requests",
+        elapsed, dispatched
+    )
+}
+
+fn requests_summary(stats: &stats::Summary) -> String {
+    let outcomes = format!(
+        "{} completed: {} succeeded, {} failed",
+        stats.total, stats.successes, stats.failures
+    );
+
+    match (stats.p50, stats.p90, stats.p99) {
+        (Some(p50), Some(p90), Some(p99)) => format!(
+            "{}\nlatency: p50 {:.2?}, p90 {:.2?}, p99 {:.2?}",
+            outcomes, p50, p90, p99
+        ),
+        _ => outcomes,
+    }
+}
+
+/// e.g. `statuses: 200: 4821, 429: 53, ERR: 2`, with requests that got no response as `ERR`.
+fn statuses_summary(stats: &stats::Summary) -> Option<String> {
+    let mut counts: Vec<_> = stats
+        .statuses
+        .iter()
+        .map(|(status, count)| format!("{}: {}", status, count))
+        .collect();
+    if stats.errors > 0 {
+        counts.push(format!("ERR: {}", stats.errors));
+    }
+
+    if counts.is_empty() {
+        return None;
+    }
+    Some(format!("statuses: {}", counts.join(", ")))
+}
+
+fn concurrency_summary(in_flight: &LevelHistogram) -> Option<String> {
+    Some(format!(
+        "in flight: min {}, mean {:.1}, max {}, p99 {}",
+        in_flight.min()?,
+        in_flight.mean()?,
+        in_flight.max()?,
+        in_flight.percentile(99.0)?,
+    ))
+}
+
+/// Duplicates that were sent anyway. Skipped ones are reported on the first line instead.
+fn duplicates_summary(duplicates: Option<DuplicateCount>) -> Option<String> {
+    match duplicates? {
+        DuplicateCount {
+            skipped: false,
+            count,
+        } => Some(format!("duplicates: {}", count)),
+        DuplicateCount { skipped: true, .. } => None,
+    }
+}
+
+fn errors_summary(errors: &[(String, u64)]) -> Option<String> {
+    if errors.is_empty() {
+        return None;
+    }
+
+    let lines: Vec<_> = errors
+        .iter()
+        .map(|(reason, count)| format!("  {} × {}", count, reason))
+        .collect();
+    Some(format!("top errors:\n{}", lines.join("\n")))
+}
+
+/// How often the number of requests in flight is sampled for the summary.
+const IN_FLIGHT_SAMPLE_PERIOD: Duration = Duration::from_millis(10);
+
+/// Serializes as the `--output json` summary, with durations as fractional milliseconds.
+#[derive(serde::Serialize)]
+pub struct Run {
+    /// Requests that used up one of `count`, whether or not they were sent
+    pub dispatched: u64,
+    pub sent: u64,
+    #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+    pub elapsed: Duration,
+    pub exit_reason: ExitReason,
+    #[serde(serialize_with = "serialize_levels")]
+    pub in_flight: LevelHistogram,
+    pub stats: stats::Summary,
+    /// The most common error reasons and their counts, see `TOP_ERRORS`
+    #[serde(serialize_with = "serialize_error_counts")]
+    pub errors: Vec<(String, u64)>,
+    /// Requests identical to an earlier one, when `--detect-duplicates` is set
+    pub duplicates: Option<DuplicateCount>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct DuplicateCount {
+    pub count: u64,
+    /// Whether they were left unsent, for `--skip-duplicates`
+    pub skipped: bool,
+}
+
+impl Run {
+    pub fn summary(&self, only_on_failure: bool, output: OutputFormat) -> Option<String> {
+        if only_on_failure && self.exit_reason == ExitReason::Success {
+            return None;
+        }
+
+        match output {
+            OutputFormat::Text => Some(self.text_summary()),
+            OutputFormat::Json => {
+                Some(serde_json::to_string_pretty(self).expect("summary should serialize to JSON"))
+            }
+        }
+    }
+
+    fn text_summary(&self) -> String {
+        let mut summary = summary(self.dispatched, self.elapsed);
+        if self.sent < self.dispatched {
+            summary.push_str(&format!(
+                " ({} duplicates not sent)",
+                self.dispatched - self.sent
+            ));
+        }
+        if self.dispatched > 0 {
+            for section in [
+                Some(requests_summary(&self.stats)),
+                statuses_summary(&self.stats),
+                concurrency_summary(&self.in_flight),
+                duplicates_summary(self.duplicates),
+                errors_summary(&self.errors),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                summary.push('\n');
+                summary.push_str(&section);
+            }
+        }
+
+        summary
+    }
+}
+
+fn serialize_millis<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+fn serialize_levels<S>(levels: &LevelHistogram, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    #[derive(serde::Serialize)]
+    struct Levels {
+        min: Option<u64>,
+        mean: Option<f64>,
+        max: Option<u64>,
+        p99: Option<u64>,
+    }
+
+    serde::Serialize::serialize(
+        &Levels {
+            min: levels.min(),
+            mean: levels.mean(),
+            max: levels.max(),
+            p99: levels.percentile(99.0),
+        },
+        serializer,
+    )
+}
+
+fn serialize_error_counts<S>(errors: &[(String, u64)], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    #[derive(serde::Serialize)]
+    struct ErrorCount<'a> {
+        reason: &'a str,
+        count: u64,
+    }
+
+    serializer.collect_seq(errors.iter().map(|(reason, count)| ErrorCount {
+        reason,
+        count: *count,
+    }))
+}
+
+/// Where a run's requests come from.
+enum Source {
+    Generate(Arc<Payload>),
+    Replay(Vec<RecordedRequest>),
+}
+
+pub async fn run(args: Args) -> Run {
+    let cancel = CancellationToken::new();
+    let run = run_with_cancel(args, MiddlewareChain::new(), cancel.clone());
+    tokio::pin!(run);
+
+    tokio::select! {
+        run = &mut run => return run,
+        _ = tokio::signal::ctrl_c() => cancel.cancel(),
+    }
+
+    run.await
+}
+
+/// Runs until every worker has finished or `cancel` fires, whichever comes first, passing every
+/// request through `middleware`.
+pub async fn run_with_cancel(
+    args: Args,
+    middleware: MiddlewareChain,
+    cancel: CancellationToken,
+) -> Run {
+    let source = match &args.replay {
+        Some(path) => load_recording(path).map(Source::Replay),
+        None => Payload::load(&args).map(|payload| Source::Generate(Arc::new(payload))),
+    };
+    let recorder = args.record.as_ref().map(|path| {
+        std::fs::File::create(path)
+            .map(|file| std::sync::Mutex::new(std::io::BufWriter::new(file)))
+            .with_context(|| format!("failed to create --record {}", path.display()))
+    });
+    let (source, recorder) = match (source, recorder.transpose()) {
+        (Ok(source), Ok(recorder)) => (source, recorder),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("error: {:#}", err);
+            return Run {
+                dispatched: 0,
+                sent: 0,
+                elapsed: Duration::ZERO,
+                exit_reason: ExitReason::ConfigError,
+                in_flight: LevelHistogram::new(),
+                stats: stats::Summary::default(),
+                errors: Vec::new(),
+                duplicates: None,
+            };
+        }
+    };
+
+    let network = NetworkConditions {
+        latency: args.simulate_latency,
+        bandwidth: args.simulate_bandwidth,
+    };
+    let detect_duplicates = args.detect_duplicates;
+    let stats = if args.approx_percentiles {
+        Stats::approximate()
+    } else {
+        Stats::new()
+    };
+    let started = Instant::now();
+    let shared = Arc::new(Shared {
+        client: reqwest::Client::new(),
+        network,
+        args,
+        started,
+        recorder,
+        middleware,
+        duplicates: detect_duplicates.then(Duplicates::default),
+        outcomes: Outcomes::default(),
+        stats,
+        dispatched: AtomicU64::new(0),
+        sent: AtomicU64::new(0),
+        in_flight: AtomicU64::new(0),
+        counter: AtomicU64::new(0),
+        count_reached: CancellationToken::new(),
+    });
+    if shared.args.count == Some(0) {
+        shared.count_reached.cancel();
+    }
+
+    let mut worker_set = JoinSet::new();
+    match source {
+        Source::Generate(payload) => {
+            if let Some(warning) = unsent_payload_warning(&shared.args) {
+                eprintln!("{}", warning);
+            }
+            let period = shared
+                .args
+                .period()
+                .expect("clap requires --every or --rate unless replaying");
+            let workers = shared.args.concurrency;
+            let period = cap_period(period, shared.args.max_rps, workers);
+
+            for worker_index in 0..workers {
+                let first_tick = started + stagger(period, worker_index, workers);
+                let ticker = if shared.args.align {
+                    Ticker::Aligned(AlignedInterval::new(period))
+                } else {
+                    Ticker::new(first_tick, period, shared.args.jitter)
+                };
+                worker_set.spawn(worker(Arc::clone(&shared), Arc::clone(&payload), ticker));
+            }
+        }
+        Source::Replay(recording) => {
+            worker_set.spawn(replay(Arc::clone(&shared), recording));
+        }
+    }
+
+    let mut in_flight = LevelHistogram::new();
+    let mut sample = tokio::time::interval(IN_FLIGHT_SAMPLE_PERIOD);
+    sample.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    {
+        let workers_done = async { while worker_set.join_next().await.is_some() {} };
+        tokio::pin!(workers_done);
+
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = &mut workers_done => break,
+                _ = sample.tick() => in_flight.record(shared.in_flight.load(Ordering::Relaxed)),
+            }
+        }
+    }
+    worker_set.shutdown().await;
+
+    if let Some(recorder) = &shared.recorder {
+        let mut recorder = recorder.lock().expect("recorder lock poisoned");
+        if let Err(err) = recorder.flush() {
+            eprintln!("error: failed to write --record file: {}", err);
+        }
+    }
+
+    let dispatched = shared.dispatched.load(Ordering::Relaxed);
+    Run {
+        dispatched,
+        sent: shared.sent.load(Ordering::Relaxed),
+        elapsed: started.elapsed(),
+        exit_reason: ExitReason::for_run(dispatched, &shared.outcomes),
+        in_flight,
+        stats: shared.stats.summarize(),
+        errors: shared.outcomes.errors.top(TOP_ERRORS),
+        duplicates: shared.duplicates.as_ref().map(|duplicates| DuplicateCount {
+            count: duplicates.count.load(Ordering::Relaxed),
+            skipped: shared.args.skip_duplicates,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser as _Parser;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_duration() {
+        let inputs = vec!["500ms", "1000000us"];
+
+        let expected_outputs = vec![Duration::from_millis(500), Duration::from_micros(1_000_000)];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            let output = parse_pacing(input).unwrap();
+            assert_eq!(Pacing::Period(expected), output);
+        }
+    }
+
+    #[test]
+    fn test_parse_compound_duration() {
+        let inputs = vec!["1s500ms", "2s250ms500us", "500ms", "1s1s"];
+
+        let expected_outputs = vec![
+            Duration::from_millis(1500),
+            Duration::from_micros(2_250_500),
+            Duration::from_millis(500),
+            Duration::from_secs(2),
+        ];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            let output = parse_pacing(input).unwrap();
+            assert_eq!(Pacing::Period(expected), output);
+        }
+
+        for input in ["1s500", "", "ms", "18446744073709551615s1s"] {
+            assert!(parse_pacing(input).is_err(), "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_long_units() {
+        let inputs = vec!["500ms", "500m", "1m30s", "2h15m", "1d", "1m500ms", "3h"];
+
+        let expected_outputs = vec![
+            Duration::from_millis(500),
+            Duration::from_secs(500 * 60),
+            Duration::from_secs(90),
+            Duration::from_secs(2 * 60 * 60 + 15 * 60),
+            Duration::from_secs(24 * 60 * 60),
+            Duration::from_millis(60_500),
+            Duration::from_secs(3 * 60 * 60),
+        ];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            let output = parse_pacing(input).unwrap();
+            assert_eq!(Pacing::Period(expected), output, "input {:?}", input);
+        }
+
+        assert_eq!(
+            parse_pacing("1.5s").unwrap(),
+            Pacing::Period(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            parse_pacing("0.5m2.5s").unwrap(),
+            Pacing::Period(Duration::from_millis(32_500))
+        );
+
+        // "ms" must not be split into minutes followed by a bare "s"
+        assert!(parse_pacing("500mss").is_err());
+        assert_eq!(parse_pacing("4hz").unwrap(), Pacing::Rate(4.0));
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        let inputs = vec!["100rps", "4hz", "3rps", "1hz", "0.5rps", "2.5hz"];
+
+        let expected_outputs = vec![
+            Duration::from_millis(10),
+            Duration::from_millis(250),
+            Duration::from_nanos(333_333_333),
+            Duration::from_secs(1),
+            Duration::from_secs(2),
+            Duration::from_millis(400),
+        ];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            let output = parse_pacing(input).unwrap();
+            assert!(matches!(output, Pacing::Rate(_)));
+            assert_eq!(expected, output.period());
+        }
+
+        let err = parse_pacing("0rps").unwrap_err();
+        assert_eq!(err.to_string(), "rate must be greater than zero");
+        assert!(parse_pacing("2000000000hz").is_err());
+        let err = parse_pacing("0.00000000000000000001rps").unwrap_err();
+        assert_eq!(err.to_string(), "rate is too low");
+    }
+
+    #[test]
+    fn test_parse_pacing_neither_form() {
+        for input in ["500", "fast", "500ms100rps", ""] {
+            let err = parse_pacing(input).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                r#"expected a duration (Ex. "500ms") or a rate (Ex. "100rps")"#
+            );
+        }
+    }
+
+    #[test]
+    fn test_cap_period() {
+        let every = parse_pacing("1000rps").unwrap().period();
+
+        assert_eq!(cap_period(every, None, 1), Duration::from_millis(1));
+        assert_eq!(cap_period(every, Some(100), 1), Duration::from_millis(10));
+        assert_eq!(cap_period(every, Some(5000), 1), Duration::from_millis(1));
+
+        assert_eq!(cap_period(every, Some(100), 4), Duration::from_millis(40));
+        assert_eq!(cap_period(every, Some(5000), 4), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_max_rps_warning() {
+        assert_eq!(
+            max_rps_warning(Duration::from_millis(1), 4, 100, Duration::from_millis(40)),
+            "warning: sending every 1ms from each of 4 workers would exceed --max-rps 100, \
+             sending every 40ms instead"
+        );
+    }
+
+    #[test]
+    fn test_claim_never_exceeds_count() {
+        let dispatched = Arc::new(AtomicU64::new(0));
+        let claimed = Arc::new(AtomicU64::new(0));
+
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let dispatched = Arc::clone(&dispatched);
+                let claimed = Arc::clone(&claimed);
+                std::thread::spawn(move || {
+                    for _ in 0..1_000 {
+                        if claim(&dispatched, Some(1_234)).is_some() {
+                            claimed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(claimed.load(Ordering::Relaxed), 1_234);
+        assert_eq!(dispatched.load(Ordering::Relaxed), 1_234);
+    }
+
+    #[test]
+    fn test_claim_without_count() {
+        let dispatched = AtomicU64::new(0);
+
+        assert!((0..1_000).all(|index| claim(&dispatched, None) == Some(index)));
+        assert_eq!(dispatched.load(Ordering::Relaxed), 1_000);
+
+        assert_eq!(claim(&AtomicU64::new(0), Some(0)), None);
+    }
+
+    #[test]
+    fn test_stagger() {
+        let period = Duration::from_millis(100);
+
+        let offsets: Vec<_> = (0..4).map(|worker| stagger(period, worker, 4)).collect();
+        assert_eq!(
+            offsets,
+            vec![
+                Duration::ZERO,
+                Duration::from_millis(25),
+                Duration::from_millis(50),
+                Duration::from_millis(75),
+            ]
+        );
+
+        assert_eq!(stagger(period, 0, 1), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_format_pacing_round_trips() {
+        let inputs = vec![
+            "2s",
+            "500ms",
+            "250us",
+            "1500ms",
+            "333333333ns",
+            "123456789123ms",
+            "18446744073709551615ns",
+            "100rps",
+            "4hz",
+        ];
+        let expected_outputs = vec![
+            "2s",
+            "500ms",
+            "250us",
+            "1500ms",
+            "333333333ns",
+            "123456789123ms",
+            "18446744073709551615ns",
+            "100rps",
+            "4rps",
+        ];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            let pacing = parse_pacing(input).unwrap();
+            let output = pacing.to_string();
+
+            assert_eq!(output, expected);
+            assert_eq!(parse_pacing(&output).unwrap(), pacing);
+        }
+    }
+
+    #[test]
+    fn test_print_config() {
+        let args = Args::try_parse_from([
+            "barrage",
+            "http://localhost:8080",
+            "--data",
+            "hello",
+            "--every",
+            "500ms",
+            "--max-rps",
+            "100",
+            "--print-config",
+        ])
+        .unwrap();
+
+        let config = serde_json::to_value(&args).unwrap();
+        assert_eq!(
+            config,
+            serde_json::json!({
+                "addr": "http://localhost:8080",
+                "data": "hello",
+                "method": "POST",
+                "headers": [],
+                "concurrency": 1,
+                "count": null,
+                "every": "500ms",
+                "rate": null,
+                "jitter": 0.0,
+                "align": false,
+                "max_rps": 100,
+                "simulate_latency": null,
+                "data_file": null,
+                "stream_body": false,
+                "simulate_bandwidth": null,
+                "record": null,
+                "replay": null,
+                "detect_duplicates": false,
+                "skip_duplicates": false,
+                "approx_percentiles": false,
+                "summary_only_on_failure": false,
+                "output": "text",
+            })
+        );
+    }
+
+    #[test]
+    fn test_request_posts_json_payload() {
+        let args = Args::try_parse_from([
+            "barrage",
+            "http://localhost:8080/ingest",
+            "--data",
+            "hello",
+            "--every",
+            "500ms",
+        ])
+        .unwrap();
+
+        let request = request(
+            &reqwest::Client::new(),
+            &args,
+            &Payload::load(&args).unwrap(),
+            &RequestCtx::new(0),
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(request.method(), reqwest::Method::POST);
+        assert_eq!(request.url().as_str(), "http://localhost:8080/ingest");
+        assert_eq!(
+            request.headers()[reqwest::header::CONTENT_TYPE],
+            "application/json"
+        );
+        assert_eq!(
+            request.body().and_then(|body| body.as_bytes()),
+            Some(br#""hello""#.as_slice())
+        );
+    }
+
+    #[test]
+    fn test_request_renders_template() {
+        let args = Args::try_parse_from([
+            "barrage",
+            "http://localhost:8080",
+            "--data",
+            "req-{{counter}}",
+            "--every",
+            "500ms",
+        ])
+        .unwrap();
+        let payload = Payload::load(&args).unwrap();
+
+        let bodies = (5..7)
+            .map(|counter| {
+                let request = request(
+                    &reqwest::Client::new(),
+                    &args,
+                    &payload,
+                    &RequestCtx::new(counter),
+                )
+                .build()
+                .unwrap();
+                request.body().unwrap().as_bytes().unwrap().to_vec()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(bodies, vec![br#""req-5""#.to_vec(), br#""req-6""#.to_vec()]);
+    }
+
+    #[test]
+    fn test_parse_data() {
+        assert_eq!(
+            parse_data(r#"{"id": 1, "tags": ["a"]}"#).unwrap(),
+            serde_json::json!({"id": 1, "tags": ["a"]})
+        );
+        assert_eq!(parse_data("42").unwrap(), serde_json::json!(42));
+        assert_eq!(parse_data("hello").unwrap(), serde_json::json!("hello"));
+
+        let path = temp_file("data.json", br#"{"nested": {"ok": true}}"#);
+        let parsed = parse_data(&format!("@{}", path.display()));
+        std::fs::write(&path, b"{not json").unwrap();
+        let invalid = parse_data(&format!("@{}", path.display()));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(parsed.unwrap(), serde_json::json!({"nested": {"ok": true}}));
+        assert_eq!(
+            invalid.unwrap_err().to_string(),
+            format!("{} is not valid JSON", path.display())
+        );
+
+        let missing = parse_data("@does/not/exist.json").unwrap_err();
+        assert_eq!(missing.to_string(), "failed to open does/not/exist.json");
+    }
+
+    #[test]
+    fn test_request_method() {
+        let request_for = |method: &str| {
+            let args = Args::try_parse_from([
+                "barrage",
+                "http://localhost:8080",
+                "--data",
+                "hello",
+                "--every",
+                "500ms",
+                "--method",
+                method,
+            ])
+            .unwrap();
+
+            request(
+                &reqwest::Client::new(),
+                &args,
+                &Payload::load(&args).unwrap(),
+                &RequestCtx::new(0),
+            )
+            .build()
+            .unwrap()
+        };
+
+        for method in ["GET", "HEAD"] {
+            let request = request_for(method);
+            assert_eq!(request.method().as_str(), method);
+            assert!(request.body().is_none());
+        }
+
+        for method in ["POST", "PUT", "PATCH", "DELETE"] {
+            let request = request_for(method);
+            assert_eq!(request.method().as_str(), method);
+            assert_eq!(
+                request.body().and_then(|body| body.as_bytes()),
+                Some(br#""hello""#.as_slice())
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_method_rejects_unknown_verbs() {
+        for input in ["FETCH", "get", "GETS", ""] {
+            let err = parse_method(input).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "expected one of GET, POST, PUT, PATCH, DELETE, HEAD"
+            );
+        }
+    }
+
+    #[test]
+    fn test_exit_reason_for_config_error() {
+        let err = Args::try_parse_from(["barrage", "http://localhost:8080", "--every", "500ms"])
+            .err()
+            .unwrap();
+        assert_eq!(ExitReason::for_config_error(&err), ExitReason::ConfigError);
+
+        let err = Args::try_parse_from(["barrage", "--help"]).err().unwrap();
+        assert_eq!(ExitReason::for_config_error(&err), ExitReason::Success);
+    }
+
+    #[tokio::test]
+    async fn test_exit_reason_for_run() {
+        let outcomes = Outcomes::default();
+        assert_eq!(ExitReason::for_run(1, &outcomes), ExitReason::Success);
+        assert_eq!(
+            ExitReason::for_run(0, &outcomes),
+            ExitReason::NoRequestsSent
+        );
+
+        // nothing listens on port 1, so connecting is refused
+        let client = reqwest::Client::new();
+        let refused = client.get("http://127.0.0.1:1").send().await;
+        assert!(refused.as_ref().unwrap_err().is_connect());
+
+        outcomes.record(&refused);
+        outcomes.record(&refused);
+        assert_eq!(
+            ExitReason::for_run(2, &outcomes),
+            ExitReason::TargetUnreachable
+        );
+
+        outcomes.responses.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(ExitReason::for_run(3, &outcomes), ExitReason::Success);
+    }
+
+    #[tokio::test]
+    async fn test_outcomes_count_distinct_errors() {
+        // accepts connections but never answers, so requests to it time out
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent_addr = format!("http://{}", silent.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            loop {
+                held.push(silent.accept().await.unwrap());
+            }
+        });
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let outcomes = Outcomes::default();
+        for _ in 0..3 {
+            outcomes.record(&client.get("http://127.0.0.1:1").send().await);
+        }
+        for _ in 0..2 {
+            outcomes.record(&client.get(&silent_addr).send().await);
+        }
+
+        let refused = error_reason(&client.get("http://127.0.0.1:1").send().await.unwrap_err());
+        assert!(!refused.contains("127.0.0.1"), "{}", refused);
+        assert_eq!(
+            outcomes.errors.top(TOP_ERRORS),
+            vec![(refused, 3), ("timed out".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_error_counts_are_capped() {
+        let errors = ErrorCounts::default();
+        for i in 0..MAX_DISTINCT_ERRORS + 10 {
+            errors.record(&format!("error {:02}", i));
+        }
+        errors.record("error 00");
+
+        let top = errors.top(usize::MAX);
+        assert_eq!(top.len(), MAX_DISTINCT_ERRORS + 1);
+        assert_eq!(top[0], ("other errors".to_string(), 10));
+        assert_eq!(top[1], ("error 00".to_string(), 2));
+        assert_eq!(errors.top(2).len(), 2);
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        for (reason, code) in [
+            (ExitReason::Success, ExitCode::SUCCESS),
+            (ExitReason::ConfigError, ExitCode::from(2)),
+            (ExitReason::TargetUnreachable, ExitCode::from(3)),
+            (ExitReason::NoRequestsSent, ExitCode::from(4)),
+        ] {
+            assert_eq!(ExitCode::from(reason), code);
+        }
+    }
+
+    #[test]
+    fn test_request_headers() {
+        let args = Args::try_parse_from([
+            "barrage",
+            "http://localhost:8080",
+            "--data",
+            "hello",
+            "--every",
+            "500ms",
+            "-H",
+            "Authorization: Bearer abc123",
+            "--header",
+            "Content-Type: application/vnd.api+json",
+        ])
+        .unwrap();
+
+        let request = request(
+            &reqwest::Client::new(),
+            &args,
+            &Payload::load(&args).unwrap(),
+            &RequestCtx::new(0),
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(request.headers()["authorization"], "Bearer abc123");
+        assert_eq!(
+            request.headers()[reqwest::header::CONTENT_TYPE],
+            "application/vnd.api+json"
+        );
+    }
+
+    #[test]
+    fn test_parse_header_errors() {
+        let err = parse_header("NoColonHere").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"expected a header in the form "Key: Value""#
+        );
+
+        let err = parse_header("Bad Name: value").unwrap_err();
+        assert_eq!(err.to_string(), "invalid header name");
+    }
+
+    #[tokio::test]
+    async fn test_run_with_zero_count() {
+        let args = Args::try_parse_from([
+            "barrage",
+            "http://127.0.0.1:1",
+            "--data",
+            "hello",
+            "--every",
+            "10ms",
+            "--count",
+            "0",
+            "--concurrency",
+            "4",
+        ])
+        .unwrap();
+
+        let run = run(args).await;
+
+        assert_eq!(run.dispatched, 0);
+        assert_eq!(run.exit_reason, ExitReason::NoRequestsSent);
+        assert_eq!(
+            summary(run.dispatched, run.elapsed),
+            "0 requests sent — check configuration"
+        );
+    }
+
+    #[test]
+    fn test_parse_bandwidth() {
+        let inputs = vec!["512b/s", "64kb/s", "10mb/s", "1gb/s"];
+        let expected_outputs = vec![512, 64_000, 10_000_000, 1_000_000_000];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            assert_eq!(
+                parse_bandwidth(input).unwrap(),
+                expected,
+                "input {:?}",
+                input
+            );
+        }
+
+        for input in ["64kb", "kb/s", "0b/s", "18446744073709551615kb/s"] {
+            assert!(parse_bandwidth(input).is_err(), "input {:?}", input);
+        }
+    }
+
+    /// Serves a single `200 OK` with a `body_len` byte body, returning the address to hit.
+    async fn serve_once(body_len: usize) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await.unwrap();
+
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body_len
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            for _ in 0..body_len / 1_000 {
+                stream.write_all(&[b'x'; 1_000]).await.unwrap();
+            }
+        });
+
+        format!("http://{}", addr)
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("barrage-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_data_file_is_read_once() {
+        let path = temp_file("payload.bin", b"not json");
+        let args = Args::try_parse_from([
+            "barrage",
+            "http://localhost:8080",
+            "--data-file",
+            path.to_str().unwrap(),
+            "--every",
+            "500ms",
+        ])
+        .unwrap();
+
+        let payload = Payload::load(&args).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let request = request(
+            &reqwest::Client::new(),
+            &args,
+            &payload,
+            &RequestCtx::new(0),
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            request.body().and_then(|body| body.as_bytes()),
+            Some(b"not json".as_slice())
+        );
+        assert!(request
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .is_none());
+    }
+
+    #[test]
+    fn test_data_and_data_file_conflict() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from(
+                ["barrage", "http://localhost:8080", "--every", "500ms"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+
+        assert!(parse(&["--data", "hello", "--data-file", "payload.json"]).is_err());
+        assert!(parse(&["--data", "hello", "--stream-body"]).is_err());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--data-file", "payload.json", "--stream-body"]).is_ok());
+    }
+
+    #[test]
+    fn test_rate() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from(
+                ["barrage", "http://localhost:8080", "--data", "hello"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+
+        let args = parse(&["--rate", "2"]).unwrap();
+        assert_eq!(args.rate, Some(Pacing::Rate(2.0)));
+        assert_eq!(args.period(), Some(Duration::from_millis(500)));
+        let args = parse(&["--rate", "3"]).unwrap();
+        assert_eq!(args.period(), Some(parse_pacing("3rps").unwrap().period()));
+        let args = parse(&["--rate", "0.5"]).unwrap();
+        assert_eq!(args.period(), Some(Duration::from_secs(2)));
+        let args = parse(&["--every", "250ms"]).unwrap();
+        assert_eq!(args.period(), Some(Duration::from_millis(250)));
+
+        assert!(parse(&["--rate", "2", "--every", "500ms"]).is_err());
+        assert!(parse(&[]).is_err());
+
+        // the same checks as rates given to `--every`
+        assert_eq!(
+            parse_rate("0").unwrap_err().to_string(),
+            parse_pacing("0rps").unwrap_err().to_string()
+        );
+        assert_eq!(
+            parse_rate("2000000000").unwrap_err().to_string(),
+            parse_pacing("2000000000rps").unwrap_err().to_string()
+        );
+        assert!(parse_rate("-1").is_err());
+        assert!(parse_rate("2rps").is_err());
+        assert!(parse_rate("0.0000000000000000000001").is_err());
+    }
+
+    #[test]
+    fn test_zero_durations_are_rejected() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from(
+                ["barrage", "http://localhost:8080", "--data", "hello"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+
+        for every in ["0s", "0ms", "0.0000000001s"] {
+            assert!(parse(&["--every", every]).is_err(), "--every {:?}", every);
+            assert!(
+                parse(&["--every", every, "--align"]).is_err(),
+                "--every {:?} --align",
+                every
+            );
+        }
+        assert!(parse(&["--every", "1s", "--simulate-latency", "0s"]).is_err());
+
+        let err = parse_pacing("0s").unwrap_err();
+        assert_eq!(err.to_string(), "duration must be greater than zero");
+        assert!(parse(&["--every", "1ns"]).is_ok());
+    }
+
+    #[test]
+    fn test_unsent_payload_warning() {
+        let warning = |args: &[&str]| {
+            let args = Args::try_parse_from(
+                ["barrage", "http://localhost:8080", "--every", "1s"]
+                    .iter()
+                    .chain(args),
+            )
+            .unwrap();
+            unsent_payload_warning(&args)
+        };
+
+        assert_eq!(
+            warning(&["--data", "1", "--method", "GET"]).as_deref(),
+            Some("warning: GET requests have no body, so --data won't be sent")
+        );
+        assert_eq!(
+            warning(&["--data-file", "a.bin", "--stream-body", "--method", "HEAD"]).as_deref(),
+            Some("warning: HEAD requests have no body, so --data-file and --stream-body won't be sent")
+        );
+        assert_eq!(warning(&["--data", "1"]), None);
+        assert_eq!(warning(&["--data-file", "a.bin", "--method", "PUT"]), None);
+    }
+
+    #[test]
+    fn test_align_conflicts_with_jitter() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from(
+                [
+                    "barrage",
+                    "http://localhost:8080",
+                    "--data",
+                    "hello",
+                    "--every",
+                    "1s",
+                ]
+                .iter()
+                .chain(args),
+            )
+        };
+
+        assert!(parse(&["--align"]).unwrap().align);
+        assert!(parse(&["--align", "--jitter", "0.5"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_body_sends_file_chunked() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+
+        let contents = vec![b'x'; 4 * 1024 * 1024];
+        let path = temp_file("streamed.bin", &contents);
+        let args = Args::try_parse_from([
+            "barrage",
+            &addr,
+            "--data-file",
+            path.to_str().unwrap(),
+            "--stream-body",
+            "--every",
+            "500ms",
+        ])
+        .unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0; 64 * 1024];
+            // a chunked body ends with an empty chunk
+            while !received.ends_with(b"\r\n0\r\n\r\n") {
+                let read = stream.read(&mut buf).await.unwrap();
+                assert!(read > 0, "connection closed before the body finished");
+                received.extend_from_slice(&buf[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            received
+        });
+
+        let payload = Payload::load(&args).unwrap();
+        let response = request(
+            &reqwest::Client::new(),
+            &args,
+            &payload,
+            &RequestCtx::new(0),
+        )
+        .send()
+        .await
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let received = server.await.unwrap();
+        let head_end = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&received[..head_end]).to_lowercase();
+
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(head.contains("transfer-encoding: chunked"), "{}", head);
+        assert!(!head.contains("content-length"), "{}", head);
+        assert!(received.len() > contents.len());
+    }
+
+    type Received = Arc<std::sync::Mutex<Vec<(String, Vec<u8>)>>>;
+
+    /// Answers every request with `200 OK`, keeping each one's head and body in arrival order.
+    async fn recording_server() -> (String, Received) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let received = Received::default();
+
+        let log = Arc::clone(&received);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+
+                let head_end = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end;
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..head_end]).to_lowercase();
+                let body_len = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .map_or(0, |len| len.parse().unwrap());
+                while request.len() < head_end + 4 + body_len {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+
+                let body = request[head_end + 4..].to_vec();
+                log.lock().unwrap().push((head, body));
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                    .await
+                    .unwrap();
+            }
+        });
+
+        (addr, received)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_stops_once_count_is_used_up() {
+        let (addr, received) = recording_server().await;
+        let args = Args::try_parse_from([
+            "barrage",
+            &addr,
+            "--data",
+            "1",
+            "--every",
+            "10s",
+            "--count",
+            "3",
+            "--concurrency",
+            "2",
+        ])
+        .unwrap();
+
+        let run = run_with_cancel(args, MiddlewareChain::new(), CancellationToken::new()).await;
+
+        // workers send at 0s, 5s and 10s, and the tick due next at 15s isn't waited for
+        assert_eq!(received.lock().unwrap().len(), 3);
+        assert_eq!(run.dispatched, 3);
+        assert!(
+            (Duration::from_secs(10)..Duration::from_millis(10_500)).contains(&run.elapsed),
+            "elapsed {:?}",
+            run.elapsed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_applies_middleware() {
+        let (addr, received) = recording_server().await;
+        let args = Args::try_parse_from([
+            "barrage",
+            &addr,
+            "--data",
+            "1",
+            "--every",
+            "10ms",
+            "--count",
+            "2",
+            "-H",
+            "X-Order: cli",
+        ])
+        .unwrap();
+
+        let middleware = MiddlewareChain::new()
+            .with(|mut request: reqwest::Request| {
+                request
+                    .headers_mut()
+                    .append("x-order", HeaderValue::from_static("first"));
+                request
+            })
+            .with(|mut request: reqwest::Request| {
+                request
+                    .headers_mut()
+                    .insert("x-signature", HeaderValue::from_static("signed"));
+                request
+            });
+        let run = run_with_cancel(args, middleware, CancellationToken::new()).await;
+
+        assert_eq!(run.dispatched, 2);
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        for (head, _) in received.iter() {
+            assert!(
+                head.contains("x-order: cli\r\nx-order: first\r\n"),
+                "{}",
+                head
+            );
+            assert!(head.contains("x-signature: signed\r\n"), "{}", head);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_detect_duplicates() {
+        let parse = |addr: &str, skip: bool| {
+            let mut args = vec![
+                "barrage",
+                addr,
+                "--data",
+                r#"{"user": "{{random_int:1-2}}", "tag": "{{random_int:7-7}}"}"#,
+                "--every",
+                "5ms",
+                "--count",
+                "10",
+                "--detect-duplicates",
+            ];
+            if skip {
+                args.push("--skip-duplicates");
+            }
+            Args::try_parse_from(args).unwrap()
+        };
+
+        let (addr, received) = recording_server().await;
+        let detecting = run(parse(&addr, false)).await;
+        let bodies: std::collections::HashSet<_> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, body)| body.clone())
+            .collect();
+
+        // only two distinct bodies can be built, so every other request repeats one of them
+        let distinct = bodies.len() as u64;
+        assert!((1..=2).contains(&distinct));
+        assert_eq!(received.lock().unwrap().len(), 10);
+        assert_eq!(
+            detecting.duplicates,
+            Some(DuplicateCount {
+                count: 10 - distinct,
+                skipped: false,
+            })
+        );
+        assert!(detecting
+            .summary(false, OutputFormat::Text)
+            .unwrap()
+            .contains(&format!("\nduplicates: {}", 10 - distinct)));
+
+        let (addr, received) = recording_server().await;
+        let skipping = run(parse(&addr, true)).await;
+        let sent = received.lock().unwrap().len() as u64;
+
+        assert!((1..=2).contains(&sent));
+        assert_eq!(skipping.dispatched, 10);
+        assert_eq!(skipping.sent, sent);
+        let summary = skipping.summary(false, OutputFormat::Text).unwrap();
+        assert!(
+            summary.starts_with(&format!(
+                "ran for {:.2?}: dispatched 10 requests ({} duplicates not sent)\n",
+                skipping.elapsed,
+                10 - sent
+            )),
+            "{}",
+            summary
+        );
+        assert!(!summary.contains("\nduplicates:"), "{}", summary);
+        let json: serde_json::Value =
+            serde_json::from_str(&skipping.summary(false, OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["dispatched"], 10);
+        assert_eq!(json["sent"], sent);
+        assert_eq!(
+            skipping.duplicates,
+            Some(DuplicateCount {
+                count: 10 - sent,
+                skipped: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let client = reqwest::Client::new();
+        let build = |body: &'static str| {
+            client
+                .post("http://localhost:8080")
+                .header("x-trace", "abc")
+                .body(body)
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(fingerprint(&build("a")), fingerprint(&build("a")));
+        assert_ne!(fingerprint(&build("a")), fingerprint(&build("b")));
+
+        let get = client.get("http://localhost:8080").build().unwrap();
+        assert!(fingerprint(&get).is_some());
+        assert_ne!(fingerprint(&get), fingerprint(&build("")));
+    }
+
+    #[test]
+    fn test_skip_duplicates_requires_detect_duplicates() {
+        let args = [
+            "barrage",
+            "http://localhost:8080",
+            "--data",
+            "hello",
+            "--every",
+            "1s",
+            "--skip-duplicates",
+        ];
+
+        assert!(Args::try_parse_from(args).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replay_sends_recorded_requests() {
+        let recording = temp_file("recording.ndjson", b"");
+        let (first_addr, first) = recording_server().await;
+        let args = Args::try_parse_from([
+            "barrage",
+            &first_addr,
+            "--data",
+            r#"{"id":"{{counter}}"}"#,
+            "-H",
+            "X-Run: original",
+            "--every",
+            "20ms",
+            "--count",
+            "3",
+            "--record",
+            recording.to_str().unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(run(args).await.dispatched, 3);
+        assert_eq!(load_recording(&recording).unwrap().len(), 3);
+
+        let (second_addr, second) = recording_server().await;
+        let args = Args::try_parse_from([
+            "barrage",
+            &second_addr,
+            "--replay",
+            recording.to_str().unwrap(),
+        ])
+        .unwrap();
+        let replayed = run(args).await;
+        std::fs::remove_file(&recording).unwrap();
+
+        assert_eq!(replayed.dispatched, 3);
+        assert_eq!(replayed.exit_reason, ExitReason::Success);
+
+        // `{{counter}}` made every body distinct, so matching bodies also means matching order
+        let bodies = |received: &Received| {
+            received
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(_, body)| body.clone())
+                .collect::<Vec<_>>()
+        };
+        let request_lines = |received: &Received| {
+            received
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(head, _)| head.lines().next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            bodies(&first),
+            [r#"{"id":"0"}"#, r#"{"id":"1"}"#, r#"{"id":"2"}"#]
+                .map(|body| body.as_bytes().to_vec())
+        );
+        assert_eq!(bodies(&first), bodies(&second));
+        assert_eq!(request_lines(&first), request_lines(&second));
+
+        for (replayed, _) in second.lock().unwrap().iter() {
+            assert!(replayed.contains("x-run: original"), "{}", replayed);
+            assert!(
+                replayed.contains("content-type: application/json"),
+                "{}",
+                replayed
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulated_latency_delays_requests() {
+        let addr = serve_once(0).await;
+        let network = NetworkConditions {
+            latency: Some(Duration::from_millis(100)),
+            bandwidth: None,
+        };
+
+        let started = Instant::now();
+        let client = reqwest::Client::new();
+        let response = network
+            .send(&client, client.get(addr).build().unwrap())
+            .await;
+
+        assert!(response.is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_simulated_bandwidth_caps_transfer_rate() {
+        let addr = serve_once(20_000).await;
+        let network = NetworkConditions {
+            latency: None,
+            bandwidth: Some(100_000),
+        };
+
+        let response = reqwest::Client::new().get(addr).send().await.unwrap();
+        let started = Instant::now();
+        let received = network.receive(response).await.unwrap();
+
+        // 20kb at 100kb/s can't take less than 200ms
+        assert_eq!(received, 20_000);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_run_reads_response_bodies() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // answers each request on a connection with its head straight away, then the body later
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicU64::new(0));
+        let accepted = Arc::clone(&connections);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    loop {
+                        let read = stream.read(&mut buf).await.unwrap();
+                        if read == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buf[..read]);
+                        if !request.ends_with(b"\r\n\r\n") {
+                            continue;
+                        }
+                        request.clear();
+
+                        stream
+                            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n")
+                            .await
+                            .unwrap();
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        stream.write_all(b"hello").await.unwrap();
+                    }
+                });
+            }
+        });
+
+        let args = Args::try_parse_from([
+            "barrage", &addr, "--data", "1", "--method", "GET", "--every", "300ms", "--count", "2",
+        ])
+        .unwrap();
+        let run = run(args).await;
+
+        assert_eq!(run.stats.successes, 2);
+        assert!(
+            run.stats.p50.unwrap() >= Duration::from_millis(200),
+            "{:?}",
+            run.stats
+        );
+        // a fully read response hands its connection back for the next request
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_fails_requests_whose_body_is_cut_off() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // promises a longer body than it sends, then closes the connection
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nhello")
+                .await
+                .unwrap();
+        });
+
+        let args = Args::try_parse_from([
+            "barrage", &addr, "--data", "1", "--method", "GET", "--every", "1s", "--count", "1",
+        ])
+        .unwrap();
+        let run = run(args).await;
+
+        assert_eq!(run.stats.total, 1);
+        assert_eq!(run.stats.successes, 0);
+        assert_eq!(run.stats.failures, 1);
+        assert_eq!(run.stats.p50, None);
+        assert_eq!(run.errors.len(), 1);
+        assert!(
+            run.errors[0].0.starts_with("reading response: "),
+            "{:?}",
+            run.errors
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_stops_when_cancelled() {
+        let args = Args::try_parse_from([
+            "barrage",
+            "http://127.0.0.1:1",
+            "--data",
+            "hello",
+            "--every",
+            "10ms",
+        ])
+        .unwrap();
+
+        let cancel = CancellationToken::new();
+        let cancel_later = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel_later.cancel();
+        });
+
+        // without a count this would run forever, so returning at all means it was cancelled
+        let run = tokio::time::timeout(
+            Duration::from_secs(5),
+            run_with_cancel(args, MiddlewareChain::new(), cancel),
+        )
+        .await
+        .expect("run should stop once cancelled");
+
+        assert!(run.dispatched > 0);
+        assert!(run.elapsed >= Duration::from_millis(100));
+        assert_eq!(run.exit_reason, ExitReason::TargetUnreachable);
+        assert_eq!(run.errors.len(), 1);
+        assert!(run.errors[0].1 > 0);
+        let summary = run.summary(false, OutputFormat::Text).unwrap();
+        assert!(
+            summary.ends_with(&format!(
+                "top errors:\n  {} × {}",
+                run.errors[0].1, run.errors[0].0
+            )),
+            "{}",
+            summary
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_reports_in_flight_concurrency() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // holds every request for a while, so all of them end up in flight together
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut request = [0; 1024];
+                    let _ = stream.read(&mut request).await.unwrap();
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .await
+                        .unwrap();
+                });
+            }
+        });
+
+        let args = Args::try_parse_from([
+            "barrage",
+            &addr,
+            "--data",
+            "hello",
+            "--every",
+            "40ms",
+            "--count",
+            "4",
+            "--concurrency",
+            "4",
+        ])
+        .unwrap();
+
+        let run = run(args).await;
+
+        assert_eq!(run.dispatched, 4);
+        assert_eq!(run.in_flight.max(), Some(4));
+        assert_eq!(run.in_flight.percentile(99.0), Some(4));
+        assert!(run
+            .summary(false, OutputFormat::Text)
+            .unwrap()
+            .ends_with("max 4, p99 4"));
+        assert_eq!(run.errors, vec![]);
+        assert_eq!(run.stats.total, 4);
+        assert_eq!(run.stats.successes, 4);
+        assert_eq!(run.stats.statuses, vec![(200, 4)]);
+        assert!(run.stats.p50.unwrap() >= Duration::from_millis(300));
+        assert!(run
+            .summary(false, OutputFormat::Text)
+            .unwrap()
+            .contains("4 completed: 4 succeeded, 0 failed\nlatency: p50 "));
+    }
+
+    #[test]
+    fn test_cli_values_ignore_surrounding_whitespace() {
+        assert_eq!(
+            parse_duration("500ms\n").unwrap(),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            parse_duration(" 500ms ").unwrap(),
+            Duration::from_millis(500)
+        );
+        assert_eq!(parse_pacing("100rps\n").unwrap(), Pacing::Rate(100.0));
+        assert_eq!(parse_method(" GET\n").unwrap(), reqwest::Method::GET);
+        assert_eq!(parse_bandwidth("64kb/s\n").unwrap(), 64_000);
+        assert_eq!(parse_jitter("0.5 ").unwrap(), 0.5);
+
+        let (name, value) = parse_header(" X-Trace: abc \n").unwrap();
+        assert_eq!(name, "x-trace");
+        assert_eq!(value, "abc");
+
+        // whitespace inside a value still isn't allowed
+        assert!(parse_duration("500 ms").is_err());
+    }
+
+    #[test]
+    fn test_parse_jitter() {
+        assert_eq!(parse_jitter("0.25").unwrap(), 0.25);
+        assert_eq!(parse_jitter("1").unwrap(), 1.0);
+        assert_eq!(parse_jitter("0").unwrap(), 0.0);
+
+        let err = parse_jitter("1.5").unwrap_err();
+        assert_eq!(err.to_string(), "jitter must be between 0.0 and 1.0");
+        assert!(parse_jitter("-0.1").is_err());
+        assert!(parse_jitter("10%").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_jittered_ticker_waits_for_first_tick() {
+        let first_tick = Instant::now() + Duration::from_millis(50);
+        let mut ticker = Ticker::new(first_tick, Duration::from_millis(100), 0.5);
+
+        ticker.tick().await;
+        assert_eq!(Instant::now(), first_tick);
+
+        // each gap after that is 100ms ± 50%
+        let mut last = Instant::now();
+        for _ in 0..100 {
+            ticker.tick().await;
+            let gap = last.elapsed();
+            assert!(
+                (Duration::from_millis(50)..=Duration::from_millis(150)).contains(&gap),
+                "gap {:?}",
+                gap
+            );
+            last = Instant::now();
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let output = summary(42, Duration::from_millis(1500));
+
+        assert_eq!(output, "ran for 1.50s: dispatched 42 requests");
+    }
+
+    #[test]
+    fn test_summary_only_on_failure() {
+        let passing = Run {
+            dispatched: 42,
+            sent: 42,
+            elapsed: Duration::from_millis(1500),
+            exit_reason: ExitReason::Success,
+            in_flight: LevelHistogram::new(),
+            stats: stats::Summary {
+                total: 42,
+                successes: 40,
+                failures: 2,
+                statuses: vec![(200, 40), (503, 2)],
+                errors: 0,
+                p50: Some(Duration::from_millis(12)),
+                p90: Some(Duration::from_millis(30)),
+                p99: Some(Duration::from_micros(81_250)),
+            },
+            errors: Vec::new(),
+            duplicates: None,
+        };
+        let failing = Run {
+            exit_reason: ExitReason::TargetUnreachable,
+            in_flight: LevelHistogram::new(),
+            stats: stats::Summary {
+                total: 42,
+                failures: 42,
+                errors: 42,
+                ..stats::Summary::default()
+            },
+            errors: vec![("Connection refused".to_string(), 42)],
+            duplicates: None,
+            ..passing
+        };
+
+        assert_eq!(passing.summary(true, OutputFormat::Text), None);
+        assert_eq!(
+            failing.summary(true, OutputFormat::Text).as_deref(),
+            Some(
+                "ran for 1.50s: dispatched 42 requests\n\
+                 42 completed: 0 succeeded, 42 failed\n\
+                 statuses: ERR: 42\n\
+                 top errors:\n  42 × Connection refused"
+            )
+        );
+        assert_eq!(
+            passing.summary(false, OutputFormat::Text).as_deref(),
+            Some(
+                "ran for 1.50s: dispatched 42 requests\n\
+                 42 completed: 40 succeeded, 2 failed\n\
+                 latency: p50 12.00ms, p90 30.00ms, p99 81.25ms\n\
+                 statuses: 200: 40, 503: 2"
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_summary() {
+        let mut in_flight = LevelHistogram::new();
+        for level in [1, 2, 2, 3] {
+            in_flight.record(level);
+        }
+        let run = Run {
+            dispatched: 5,
+            sent: 5,
+            elapsed: Duration::from_millis(1500),
+            exit_reason: ExitReason::Success,
+            in_flight,
+            stats: stats::Summary {
+                total: 5,
+                successes: 3,
+                failures: 2,
+                statuses: vec![(200, 3), (503, 1)],
+                errors: 1,
+                p50: Some(Duration::from_millis(12)),
+                p90: Some(Duration::from_millis(30)),
+                p99: Some(Duration::from_micros(81_250)),
+            },
+            errors: vec![("timed out".to_string(), 1)],
+            duplicates: Some(DuplicateCount {
+                count: 2,
+                skipped: false,
+            }),
+        };
+
+        let output = run.summary(false, OutputFormat::Json).unwrap();
+        let summary: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+        assert_eq!(
+            summary,
+            serde_json::json!({
+                "dispatched": 5,
+                "sent": 5,
+                "elapsed_ms": 1500.0,
+                "exit_reason": "success",
+                "in_flight": {"min": 1, "mean": 2.0, "max": 3, "p99": 3},
+                "stats": {
+                    "total": 5,
+                    "successes": 3,
+                    "failures": 2,
+                    "statuses": {"200": 3, "503": 1},
+                    "errors": 1,
+                    "p50_ms": 12.0,
+                    "p90_ms": 30.0,
+                    "p99_ms": 81.25,
+                },
+                "duplicates": {"count": 2, "skipped": false},
+                "errors": [{"reason": "timed out", "count": 1}],
+            })
+        );
+        assert_eq!(run.summary(true, OutputFormat::Json), None);
+
+        let args = Args::try_parse_from([
+            "barrage",
+            "http://localhost:8080",
+            "--data",
+            "hello",
+            "--every",
+            "1s",
+            "--output",
+            "json",
+        ])
+        .unwrap();
+        assert_eq!(args.output, OutputFormat::Json);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_rps_caps_achieved_rate() {
+        let every = parse_pacing("1000rps").unwrap().period();
+        let mut interval = tokio::time::interval(cap_period(every, Some(100), 1));
+
+        let start = Instant::now();
+        let mut ticks = 0;
+        while start.elapsed() < Duration::from_secs(1) {
+            interval.tick().await;
+            ticks += 1;
+        }
+
+        // the first tick fires immediately, so one second holds 100 periods plus that tick
+        assert_eq!(ticks, 101);
+    }
+}
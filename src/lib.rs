@@ -0,0 +1,12 @@
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "cli")]
+pub mod middleware;
+pub mod parsers;
+pub mod stats;
+#[cfg(feature = "cli")]
+pub mod template;
+#[cfg(feature = "cli")]
+pub mod ticker;
+#[cfg(feature = "cli")]
+pub mod weighted;
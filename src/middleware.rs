@@ -0,0 +1,131 @@
+use futures::future::BoxFuture;
+use reqwest::Request;
+
+/// Rewrites a request just before it is sent, e.g. to sign it or add tracing headers.
+///
+/// `apply` returns a boxed future rather than being an `async fn` so chains can hold
+/// middleware as trait objects.
+pub trait RequestMiddleware: Send + Sync {
+    fn apply<'a>(&'a self, request: Request) -> BoxFuture<'a, Request>;
+}
+
+impl<F> RequestMiddleware for F
+where
+    F: Fn(Request) -> Request + Send + Sync,
+{
+    fn apply<'a>(&'a self, request: Request) -> BoxFuture<'a, Request> {
+        let request = self(request);
+        Box::pin(async move { request })
+    }
+}
+
+/// Middleware applied one after another, in the order they were added. Pass one to
+/// `cli::run_with_cancel` to apply it to every request of a run.
+#[derive(Default)]
+pub struct MiddlewareChain {
+    middleware: Vec<Box<dyn RequestMiddleware>>,
+}
+
+impl MiddlewareChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<M>(mut self, middleware: M) -> Self
+    where
+        M: RequestMiddleware + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middleware.is_empty()
+    }
+
+    pub async fn apply(&self, mut request: Request) -> Request {
+        for middleware in &self.middleware {
+            request = middleware.apply(request).await;
+        }
+
+        request
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use reqwest::header::{HeaderName, HeaderValue};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn with_header(name: &'static str, value: &'static str) -> impl RequestMiddleware {
+        move |mut request: Request| {
+            request.headers_mut().append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+            request
+        }
+    }
+
+    /// Appends the request's own method to a header, proving middleware can be async.
+    struct EchoMethod;
+
+    impl RequestMiddleware for EchoMethod {
+        fn apply<'a>(&'a self, mut request: Request) -> BoxFuture<'a, Request> {
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                let method = HeaderValue::from_str(request.method().as_str()).unwrap();
+                request
+                    .headers_mut()
+                    .append(HeaderName::from_static("x-order"), method);
+                request
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_headers_are_sent() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            let mut buf = [0; 1024];
+            while !head.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buf).await.unwrap();
+                head.extend_from_slice(&buf[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(head).unwrap().to_lowercase()
+        });
+
+        let chain = MiddlewareChain::new()
+            .with(with_header("x-order", "first"))
+            .with(EchoMethod)
+            .with(with_header("x-request-id", "abc123"));
+
+        let client = reqwest::Client::new();
+        let request = client.get(&addr).build().unwrap();
+        let response = client.execute(chain.apply(request).await).await.unwrap();
+        let head = server.await.unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+        assert!(
+            head.contains("x-order: first\r\nx-order: get\r\n"),
+            "{}",
+            head
+        );
+        assert!(head.contains("x-request-id: abc123\r\n"), "{}", head);
+    }
+
+    #[test]
+    fn test_empty_chain() {
+        assert!(MiddlewareChain::new().is_empty());
+        assert!(!MiddlewareChain::new().with(EchoMethod).is_empty());
+    }
+}
@@ -1,3 +1,5 @@
+use std::{fmt::Display, marker::PhantomData, ops::Range, time::Duration};
+
 use anyhow::Context;
 
 pub type ParseResult<'input, O> = anyhow::Result<(&'input str, O)>;
@@ -26,6 +28,95 @@ pub trait Parser<'input, O>: Sized {
         }
     }
 
+    /// Like `map`, but also hands `transform` the byte range that was consumed, relative to the
+    /// input this parser was given.
+    fn map_with_span<F, O2>(self, transform: F) -> impl Parser<'input, O2>
+    where
+        F: Fn(O, Range<usize>) -> O2,
+    {
+        move |input: &'input str| {
+            let (rest, output) = self.parse(input)?;
+            let span = 0..input.len() - rest.len();
+            Ok((rest, transform(output, span)))
+        }
+    }
+
+    fn filter_map<F, O2>(self, f: F) -> impl Parser<'input, O2>
+    where
+        F: Fn(O) -> Option<O2>,
+    {
+        move |input| {
+            let (rest, output) = self.parse(input)?;
+            f(output)
+                .map(|output| (rest, output))
+                .ok_or_else(|| anyhow::format_err!("parsed value was rejected by filter"))
+        }
+    }
+
+    fn or_else<F, P2>(self, f: F) -> impl Parser<'input, O>
+    where
+        F: Fn(&anyhow::Error) -> P2,
+        P2: Parser<'input, O>,
+    {
+        move |input| match self.parse(input) {
+            Ok(inner) => Ok(inner),
+            Err(err) => f(&err).parse(input),
+        }
+    }
+
+    /// Tries `other` against the same input if `self` fails. When both fail, the error from
+    /// `other` is returned.
+    fn or<P>(self, other: P) -> impl Parser<'input, O>
+    where
+        P: Parser<'input, O>,
+    {
+        move |input| self.parse(input).or_else(|_| other.parse(input))
+    }
+
+    /// Never fails: a miss yields `None` and leaves the input untouched.
+    fn optional(self) -> impl Parser<'input, Option<O>> {
+        move |input| match self.parse(input) {
+            Ok((rest, output)) => Ok((rest, Some(output))),
+            Err(_) => Ok((input, None)),
+        }
+    }
+
+    /// Skips whitespace before and after `self`. Errors from `self` are passed through as is.
+    fn trim(self) -> impl Parser<'input, O> {
+        move |input| {
+            let (rest, _) = whitespace().parse(input)?;
+            let (rest, output) = self.parse(rest)?;
+            let (rest, _) = whitespace().parse(rest)?;
+            Ok((rest, output))
+        }
+    }
+
+    fn collect_string(self) -> impl Parser<'input, String>
+    where
+        O: IntoIterator,
+        String: FromIterator<O::Item>,
+    {
+        self.map(|items| items.into_iter().collect())
+    }
+
+    fn repeated(self) -> Repeated<Self, O> {
+        Repeated {
+            parser: self,
+            min: 0,
+            max: None,
+            output: PhantomData,
+        }
+    }
+
+    fn boxed(self) -> BoxedParser<'input, O>
+    where
+        Self: 'input,
+    {
+        BoxedParser {
+            parser: Box::new(move |input| self.parse(input)),
+        }
+    }
+
     fn end(self) -> impl Parser<'input, O> {
         self.then(end()).map(|(out, _)| out)
     }
@@ -43,7 +134,10 @@ where
 impl<'input> Parser<'input, &'input str> for &'static str {
     fn parse(&self, input: &'input str) -> ParseResult<'input, &'input str> {
         if let Some(rest) = input.strip_prefix(self) {
-            let found = &input[..self.len()];
+            // `self.len()` is a byte length, not a char count. Since `strip_prefix` only
+            // succeeds when `self` is a prefix of `input`, the slice always ends on a char
+            // boundary, including for multibyte literals.
+            let found = &input[..input.len() - rest.len()];
             Ok((rest, found))
         } else {
             Err(anyhow::format_err!(
@@ -54,6 +148,72 @@ impl<'input> Parser<'input, &'input str> for &'static str {
     }
 }
 
+/// Type-erased parser, for holding parsers of different concrete types together.
+pub struct BoxedParser<'input, O> {
+    parser: Box<dyn Fn(&'input str) -> ParseResult<'input, O> + 'input>,
+}
+
+impl<'input, O> Parser<'input, O> for BoxedParser<'input, O> {
+    fn parse(&self, input: &'input str) -> ParseResult<'input, O> {
+        (self.parser)(input)
+    }
+}
+
+pub struct Repeated<P, O> {
+    parser: P,
+    min: usize,
+    max: Option<usize>,
+    output: PhantomData<fn() -> O>,
+}
+
+impl<P, O> Repeated<P, O> {
+    pub fn at_least(self, min: usize) -> Self {
+        Self { min, ..self }
+    }
+
+    pub fn at_most(self, max: usize) -> Self {
+        Self {
+            max: Some(max),
+            ..self
+        }
+    }
+
+    pub fn exactly(self, count: usize) -> Self {
+        self.at_least(count).at_most(count)
+    }
+}
+
+impl<'input, P, O> Parser<'input, Vec<O>> for Repeated<P, O>
+where
+    P: Parser<'input, O>,
+{
+    fn parse(&self, input: &'input str) -> ParseResult<'input, Vec<O>> {
+        let mut outputs = Vec::new();
+        let mut remaining = input;
+
+        while self.max.is_none_or(|max| outputs.len() < max) {
+            match self.parser.parse(remaining) {
+                // stop on a match that consumed nothing, or this would loop forever
+                Ok((rest, output)) if rest.len() < remaining.len() => {
+                    outputs.push(output);
+                    remaining = rest;
+                }
+                _ => break,
+            }
+        }
+
+        if outputs.len() < self.min {
+            Err(anyhow::format_err!(
+                "expected at least {} repetitions, found {}",
+                self.min,
+                outputs.len()
+            ))
+        } else {
+            Ok((remaining, outputs))
+        }
+    }
+}
+
 pub fn literal(expected: &'static str) -> impl for<'a> Parser<'a, &'a str> {
     expected
 }
@@ -76,6 +236,16 @@ pub fn numeric<'input>() -> impl Parser<'input, &'input str> {
     match_char_where(|c| c.is_numeric())
 }
 
+/// One or more ASCII digits. Unlike `one_or_more(numeric())`, digits from other scripts such as
+/// `١٢` aren't accepted, and the error names the character that didn't match.
+fn digits<'input>() -> impl Parser<'input, &'input str> {
+    let digit = || match_char_where(|c| c.is_ascii_digit());
+    move |input| {
+        digit().parse(input)?;
+        one_or_more(digit()).parse(input)
+    }
+}
+
 fn one_or_more<'input, P, O>(parser: P) -> impl Parser<'input, &'input str>
 where
     P: Parser<'input, O>,
@@ -99,11 +269,382 @@ where
     }
 }
 
+/// Collects zero or more matches of `parser`. Shorthand for `parser.repeated()`.
+pub fn many0<'input, P, O>(parser: P) -> impl Parser<'input, Vec<O>>
+where
+    P: Parser<'input, O>,
+{
+    parser.repeated()
+}
+
+/// Collects one or more matches of `parser`, failing if there are none.
+pub fn many1<'input, P, O>(parser: P) -> impl Parser<'input, Vec<O>>
+where
+    P: Parser<'input, O>,
+{
+    parser.repeated().at_least(1)
+}
+
+/// Consumes any amount of whitespace, including none.
+pub fn whitespace<'input>() -> impl Parser<'input, &'input str> {
+    move |input: &'input str| {
+        let (rest, _) = many0(match_char_where(char::is_whitespace)).parse(input)?;
+        Ok((rest, &input[..input.len() - rest.len()]))
+    }
+}
+
 pub fn uint<'input>() -> impl Parser<'input, u64> {
-    one_or_more(numeric()).map(|out| {
-        out.parse()
-            .expect("output from numeric should parse to int without issue")
-    })
+    move |input| {
+        let (rest, digits) = digits().parse(input)?;
+        let value = digits.parse().context("integer does not fit in u64")?;
+        Ok((rest, value))
+    }
+}
+
+/// Like `uint`, but only accepts values in `min..=max`, narrowed to `T`. For example
+/// `bounded_uint(1u16, 65535)` for a port.
+pub fn bounded_uint<'input, T>(min: T, max: T) -> impl Parser<'input, T>
+where
+    T: TryFrom<u64> + PartialOrd + Display + Copy,
+{
+    move |input| {
+        let (rest, value) = uint().parse(input)?;
+        match T::try_from(value) {
+            Ok(value) if min <= value && value <= max => Ok((rest, value)),
+            _ => anyhow::bail!("integer {} is not between {} and {}", value, min, max),
+        }
+    }
+}
+
+/// Like `uint`, but accepts a leading `-`.
+pub fn int<'input>() -> impl Parser<'input, i64> {
+    move |input: &'input str| {
+        let (rest, _) = literal("-").optional().then(digits()).parse(input)?;
+
+        let value = input[..input.len() - rest.len()]
+            .parse()
+            .context("integer does not fit in i64")?;
+        Ok((rest, value))
+    }
+}
+
+/// Parses a decimal number such as `2`, `1.5` or `.25`. Exponents and signs aren't accepted.
+pub fn float<'input>() -> impl Parser<'input, f64> {
+    move |input: &'input str| {
+        let (rest, _) = digits()
+            .optional()
+            .then(literal(".").then(digits()).optional())
+            .parse(input)?;
+
+        let consumed = &input[..input.len() - rest.len()];
+        anyhow::ensure!(!consumed.is_empty(), "expected a decimal number");
+
+        let value = consumed
+            .parse()
+            .expect("digits with an optional fraction should parse to float without issue");
+        Ok((rest, value))
+    }
+}
+
+/// Parses a duration made of one or more `<amount><unit>` segments, such as `500ms` or `1m30s`.
+pub fn duration<'input>() -> impl Parser<'input, Duration> {
+    try_fold_many(
+        duration_segment(),
+        || None,
+        |total: Option<Duration>, segment| {
+            total
+                .unwrap_or_default()
+                .checked_add(segment)
+                .map(Some)
+                .context("duration is too long")
+        },
+    )
+    .filter_map(|total| total)
+}
+
+fn duration_segment<'input>() -> impl Parser<'input, Duration> {
+    // whole units are counted exactly, only the part after the point goes through f64
+    let fraction = |input: &'input str| {
+        let (rest, _) = literal(".").then(digits()).parse(input)?;
+        let value: f64 = input[..input.len() - rest.len()]
+            .parse()
+            .expect("a point followed by digits should parse to float without issue");
+        Ok((rest, value))
+    };
+
+    uint()
+        .optional()
+        .then(fraction.optional())
+        .then(crate::one_of! {
+            "s" => Duration::from_secs(1),
+            // "ms" has to be tried before "m", or "500ms" would read as 500 minutes and a stray "s"
+            "ms" => Duration::from_millis(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(60 * 60),
+            "d" => Duration::from_secs(24 * 60 * 60),
+            "ns" => Duration::from_nanos(1),
+            "us" => Duration::from_micros(1),
+        })
+        .filter_map(|((whole, fraction), unit)| {
+            if whole.is_none() && fraction.is_none() {
+                return None;
+            }
+
+            let nanos = unit.as_nanos() * u128::from(whole.unwrap_or(0));
+            let whole = Duration::new(
+                u64::try_from(nanos / 1_000_000_000).ok()?,
+                (nanos % 1_000_000_000) as u32,
+            );
+            let fraction = match fraction {
+                Some(fraction) => {
+                    Duration::try_from_secs_f64(fraction * unit.as_secs_f64()).ok()?
+                }
+                None => Duration::ZERO,
+            };
+            whole.checked_add(fraction)
+        })
+}
+
+/// A duration that may be negative, e.g. an offset before some point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedDuration {
+    pub negative: bool,
+    pub magnitude: Duration,
+}
+
+/// Like `duration`, but accepts a leading `-` or `+`. Durations without a sign are positive.
+pub fn signed_duration<'input>() -> impl Parser<'input, SignedDuration> {
+    crate::one_of! { "-" => true, "+" => false }
+        .optional()
+        .then(duration())
+        .map(|(negative, magnitude)| SignedDuration {
+            // "-0s" is the same offset as "0s"
+            negative: negative.unwrap_or(false) && !magnitude.is_zero(),
+            magnitude,
+        })
+}
+
+pub fn parse_signed_duration(s: &str) -> anyhow::Result<SignedDuration> {
+    parse_trimmed(signed_duration(), s)
+        .context(r#"expected a duration, optionally signed (Ex. "-500ms")"#)
+}
+
+/// Parses one `item`, then as many `sep` followed by `item` pairs as match. A trailing `sep`
+/// with no item after it is left in `rest`.
+pub fn separated_list<'input, P, S, O, O2>(item: P, sep: S) -> impl Parser<'input, Vec<O>>
+where
+    P: Parser<'input, O>,
+    S: Parser<'input, O2>,
+{
+    move |input| {
+        let (mut remaining, first) = item.parse(input).context("expected at least one item")?;
+        let mut items = vec![first];
+
+        while let Ok((rest, _)) = sep.parse(remaining) {
+            match item.parse(rest) {
+                Ok((rest, next)) => {
+                    items.push(next);
+                    remaining = rest;
+                }
+                Err(_) => break,
+            }
+        }
+
+        Ok((remaining, items))
+    }
+}
+
+pub fn sep_count<'input, P, S, O, O2>(item: P, sep: S) -> impl Parser<'input, usize>
+where
+    P: Parser<'input, O>,
+    S: Parser<'input, O2>,
+{
+    separated_list(item, sep).map(|items| items.len())
+}
+
+pub fn take_until_or_eof<'input>(pattern: &'static str) -> impl Parser<'input, &'input str> {
+    move |input: &'input str| {
+        let end = input.find(pattern).unwrap_or(input.len());
+        Ok((&input[end..], &input[..end]))
+    }
+}
+
+pub fn kv_map<'input>(sep: char) -> impl Parser<'input, Vec<(String, String)>> {
+    move |input| {
+        let pair = |input| -> ParseResult<'input, (String, String)> {
+            let (rest, entry) = take_until_or_eof(",").parse(input)?;
+            let (key, value) = entry
+                .split_once(sep)
+                .with_context(|| format!("expected `key{}value`, found `{}`", sep, entry))?;
+
+            let key = key.trim();
+            anyhow::ensure!(!key.is_empty(), "missing key in `{}`", entry);
+
+            Ok((rest, (key.to_string(), value.trim().to_string())))
+        };
+
+        separated_list(pair, literal(",")).parse(input)
+    }
+}
+
+/// Parses a list of items separated by any mix of commas and whitespace (including newlines),
+/// so the same values can come from a CLI argument or a file. Separators before the first and
+/// after the last item are consumed.
+pub fn list_flexible<'input, P, O>(item: P) -> impl Parser<'input, Vec<O>>
+where
+    P: Parser<'input, O>,
+{
+    move |input| {
+        let separator = one_or_more(match_char_where(|c| c == ',' || c.is_whitespace()));
+        let skip_separator = |input| match separator.parse(input) {
+            Ok((rest, _)) => rest,
+            Err(_) => input,
+        };
+
+        let (remaining, items) =
+            separated_list(|input| item.parse(input), |input| separator.parse(input))
+                .parse(skip_separator(input))?;
+
+        Ok((skip_separator(remaining), items))
+    }
+}
+
+/// Parses `open`, zero or more `sep` separated items, then `close`, e.g. `[1, 2, 3]` or `[]`.
+/// Only the items are returned.
+pub fn delimited_list<'input, Open, P, S, Close, O, O1, O2, O3>(
+    open: Open,
+    item: P,
+    sep: S,
+    close: Close,
+) -> impl Parser<'input, Vec<O>>
+where
+    Open: Parser<'input, O1>,
+    P: Parser<'input, O>,
+    S: Parser<'input, O2>,
+    Close: Parser<'input, O3>,
+{
+    move |input| {
+        let (remaining, _) = open.parse(input).context("expected opening delimiter")?;
+        let (remaining, items) =
+            separated_list(|input| item.parse(input), |input| sep.parse(input))
+                .optional()
+                .map(Option::unwrap_or_default)
+                .parse(remaining)?;
+
+        let (rest, _) = close
+            .parse(remaining)
+            .context("expected closing delimiter")?;
+
+        Ok((rest, items))
+    }
+}
+
+pub fn rest<'input>() -> impl Parser<'input, &'input str> {
+    |input: &'input str| Ok((&input[input.len()..], input))
+}
+
+/// Consumes a run of URL characters, decoding `%XX` escapes. Stops at whitespace and at the
+/// query delimiters `&`, `=` and `#`, so it can be used for a single query key or value. `+`
+/// is left as is rather than decoded to a space.
+pub fn percent_decode<'input>() -> impl Parser<'input, String> {
+    move |input: &'input str| {
+        let is_url_char = |c: char| c.is_ascii_alphanumeric() || "-._~!$'()*+,;:@/?%".contains(c);
+        let end = input.find(|c| !is_url_char(c)).unwrap_or(input.len());
+        let (encoded, rest) = input.split_at(end);
+
+        let mut decoded = Vec::with_capacity(encoded.len());
+        let mut bytes = encoded.bytes().enumerate();
+        while let Some((offset, byte)) = bytes.next() {
+            if byte != b'%' {
+                decoded.push(byte);
+                continue;
+            }
+
+            let escape = encoded.get(offset + 1..offset + 3);
+            // `from_str_radix` would also accept a sign, as in `%+1`
+            let value = escape
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .with_context(|| format!("malformed percent escape at offset {}", offset))?;
+            decoded.push(value);
+            bytes.nth(1);
+        }
+
+        let decoded = String::from_utf8(decoded).context("percent-decoded bytes are not UTF-8")?;
+        Ok((rest, decoded))
+    }
+}
+
+/// Splits a `Key: Value` header at the first colon, skipping whitespace before the value.
+pub fn header<'input>() -> impl Parser<'input, (&'input str, &'input str)> {
+    take_until_or_eof(":")
+        .then(literal(":"))
+        .then(whitespace())
+        .then(rest())
+        .map(|(((name, _), _), value)| (name, value))
+}
+
+/// Runs `parser` over the whole of `input`, ignoring surrounding whitespace such as the trailing
+/// newline of a value read from a shell variable.
+pub fn parse_trimmed<'input, P, O>(parser: P, input: &'input str) -> anyhow::Result<O>
+where
+    P: Parser<'input, O>,
+{
+    parser.trim().end().parse(input).map(|(_, out)| out)
+}
+
+pub fn try_fold_many<'input, P, O, Acc, Init, F, E>(
+    parser: P,
+    init: Init,
+    f: F,
+) -> impl Parser<'input, Acc>
+where
+    P: Parser<'input, O>,
+    Init: Fn() -> Acc,
+    F: Fn(Acc, O) -> Result<Acc, E>,
+    E: Into<anyhow::Error>,
+{
+    move |input: &'input str| {
+        let mut acc = init();
+        let mut remaining = input;
+        let mut index = 0;
+
+        while let Ok((rest, output)) = parser.parse(remaining) {
+            acc = f(acc, output).map_err(Into::into).with_context(|| {
+                format!(
+                    "fold failed at item {} (offset {})",
+                    index,
+                    input.len() - remaining.len()
+                )
+            })?;
+
+            if rest.len() == remaining.len() {
+                break;
+            }
+            remaining = rest;
+            index += 1;
+        }
+
+        Ok((remaining, acc))
+    }
+}
+
+/// Runs every parser against the same input and keeps the result that consumed the most,
+/// preferring earlier parsers on ties.
+pub fn longest_of<'input, O>(parsers: Vec<BoxedParser<'input, O>>) -> impl Parser<'input, O> {
+    move |input: &'input str| {
+        parsers
+            .iter()
+            .filter_map(|parser| parser.parse(input).ok())
+            .reduce(|longest, next| {
+                if next.0.len() < longest.0.len() {
+                    next
+                } else {
+                    longest
+                }
+            })
+            .ok_or_else(|| anyhow::format_err!("none of the provided parsers succeeded"))
+    }
 }
 
 pub fn end<'input>() -> impl Parser<'input, ()> {
@@ -136,6 +677,20 @@ macro_rules! one_of {
     };
 }
 
+#[macro_export]
+macro_rules! dispatch {
+    ($($literal:expr => $parser:expr),* $(,)?) => {
+        move |input| {
+        $(
+            if let Ok((rest, _)) = <_ as $crate::parsers::Parser<'_, _>>::parse(&$literal, input) {
+                return <_ as $crate::parsers::Parser<'_, _>>::parse(&$parser, rest);
+            }
+        )*
+            Err(anyhow::format_err!("none of the provided literals matched"))
+        }
+    };
+}
+
 #[cfg(test)]
 mod test {
     use std::time::Duration;
@@ -153,6 +708,17 @@ mod test {
         assert!(literal("goodbye").parse(input).is_err());
     }
 
+    #[test]
+    fn test_literal_multibyte() {
+        let input = "café bar";
+        let (rest, output) = literal("café").parse(input).unwrap();
+
+        assert_eq!("café".len(), 5);
+        assert_eq!(output, "café");
+        assert_eq!(rest, " bar");
+        assert!(literal("cafe").parse(input).is_err());
+    }
+
     #[test]
     fn test_numeric() {
         let input = "123";
@@ -162,6 +728,249 @@ mod test {
         assert_eq!(rest, "23");
     }
 
+    #[test]
+    fn test_uint_overflow_is_an_error() {
+        let (rest, output) = uint().parse("18446744073709551615").unwrap();
+        assert_eq!(output, u64::MAX);
+        assert_eq!(rest, "");
+
+        let err = uint().parse("99999999999999999999").unwrap_err();
+        assert_eq!(err.to_string(), "integer does not fit in u64");
+    }
+
+    #[test]
+    fn test_integers_are_ascii_digits_only() {
+        let err = uint().parse("١٢").unwrap_err();
+        assert_eq!(err.to_string(), "`١` does not satisfy predicate");
+
+        let err = int().parse("-١٢").unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "`١` does not satisfy predicate"
+        );
+
+        let err = uint().parse("ms").unwrap_err();
+        assert_eq!(err.to_string(), "`m` does not satisfy predicate");
+    }
+
+    #[test]
+    fn test_bounded_uint() {
+        let (rest, output) = bounded_uint(1u16, 65535).parse("8080/api").unwrap();
+        assert_eq!(output, 8080u16);
+        assert_eq!(rest, "/api");
+
+        let (_, output) = bounded_uint(0u8, 100).parse("100").unwrap();
+        assert_eq!(output, 100u8);
+
+        let err = bounded_uint(1u16, 65535).parse("0").unwrap_err();
+        assert_eq!(err.to_string(), "integer 0 is not between 1 and 65535");
+
+        let err = bounded_uint(0u8, 100).parse("101").unwrap_err();
+        assert_eq!(err.to_string(), "integer 101 is not between 0 and 100");
+
+        // out of range for the narrowed type, not just the bounds
+        let err = bounded_uint(0u8, 255).parse("256").unwrap_err();
+        assert_eq!(err.to_string(), "integer 256 is not between 0 and 255");
+
+        assert!(bounded_uint(0u8, 100).parse("x").is_err());
+    }
+
+    #[test]
+    fn test_int() {
+        let inputs = vec!["-42", "0", "17ms", "-9223372036854775808"];
+        let expected_outputs = vec![("", -42), ("", 0), ("ms", 17), ("", i64::MIN)];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            assert_eq!(int().parse(input).unwrap(), expected, "input {:?}", input);
+        }
+
+        for input in ["-", "", "--1", "9223372036854775808"] {
+            assert!(int().parse(input).is_err(), "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_whole_durations_are_exact() {
+        let inputs = vec![
+            "123456789123ms",
+            "123456789123456789ns",
+            "18446744073709551615ns",
+            "18446744073709551615s",
+            "1.5s",
+            ".25s",
+            "2.5m",
+        ];
+        let expected_outputs = vec![
+            Duration::from_millis(123_456_789_123),
+            Duration::from_nanos(123_456_789_123_456_789),
+            Duration::from_nanos(u64::MAX),
+            Duration::from_secs(u64::MAX),
+            Duration::from_millis(1500),
+            Duration::from_millis(250),
+            Duration::from_secs(150),
+        ];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            assert_eq!(
+                duration().end().parse(input).unwrap(),
+                ("", expected),
+                "input {:?}",
+                input
+            );
+        }
+
+        for input in [
+            "18446744073709551616s",
+            "18446744073709551615m",
+            ".s",
+            "10.s",
+        ] {
+            assert!(duration().end().parse(input).is_err(), "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_signed_duration() {
+        let inputs = vec!["-500ms", "+2s", "1m30s", "-0s"];
+        let expected_outputs = vec![
+            SignedDuration {
+                negative: true,
+                magnitude: Duration::from_millis(500),
+            },
+            SignedDuration {
+                negative: false,
+                magnitude: Duration::from_secs(2),
+            },
+            SignedDuration {
+                negative: false,
+                magnitude: Duration::from_secs(90),
+            },
+            SignedDuration {
+                negative: false,
+                magnitude: Duration::ZERO,
+            },
+        ];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            assert_eq!(
+                parse_signed_duration(input).unwrap(),
+                expected,
+                "input {:?}",
+                input
+            );
+        }
+
+        for input in ["-", "--1s", "+-1s", "500"] {
+            assert!(parse_signed_duration(input).is_err(), "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_float() {
+        let inputs = vec!["1.5s", "2", ".25", "10.", "1.25.5", "0.5e3"];
+        let expected_outputs = vec![
+            ("s", 1.5),
+            ("", 2.0),
+            ("", 0.25),
+            (".", 10.0),
+            (".5", 1.25),
+            ("e3", 0.5),
+        ];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            assert_eq!(float().parse(input).unwrap(), expected, "input {:?}", input);
+        }
+
+        for input in ["", ".", "s", "-1"] {
+            assert!(float().parse(input).is_err(), "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_separated_list() {
+        let (rest, output) = separated_list(uint(), literal(",")).parse("1,2,3").unwrap();
+        assert_eq!(output, vec![1, 2, 3]);
+        assert_eq!(rest, "");
+
+        // a trailing separator isn't consumed, so the caller decides whether it is an error
+        let (rest, output) = separated_list(uint(), literal(",")).parse("1,2,").unwrap();
+        assert_eq!(output, vec![1, 2]);
+        assert_eq!(rest, ",");
+        assert!(separated_list(uint(), literal(","))
+            .end()
+            .parse("1,2,")
+            .is_err());
+
+        let err = separated_list(uint(), literal(","))
+            .parse(",1")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "expected at least one item");
+    }
+
+    #[test]
+    fn test_delimited_list() {
+        let list = || delimited_list("[", uint(), ", ", "]");
+
+        let (rest, output) = list().parse("[1, 2, 3] tail").unwrap();
+        assert_eq!(output, vec![1, 2, 3]);
+        assert_eq!(rest, " tail");
+
+        let (rest, output) = list().parse("[]").unwrap();
+        assert_eq!(output, Vec::<u64>::new());
+        assert_eq!(rest, "");
+
+        let err = list().parse("[1, 2").unwrap_err();
+        assert_eq!(err.to_string(), "expected closing delimiter");
+        assert!(list().parse("[1, 2, ]").is_err());
+        assert!(list().parse("1, 2]").is_err());
+    }
+
+    #[test]
+    fn test_many() {
+        let (rest, output) = many1(numeric()).parse("123ms").unwrap();
+        assert_eq!(output, vec!["1", "2", "3"]);
+        assert_eq!(rest, "ms");
+
+        let (rest, output) = many0(numeric()).parse("ms").unwrap();
+        assert_eq!(output, Vec::<&str>::new());
+        assert_eq!(rest, "ms");
+
+        assert!(many1(numeric()).parse("ms").is_err());
+    }
+
+    #[test]
+    fn test_whitespace() {
+        let (rest, output) = whitespace().parse(" \t\n 42").unwrap();
+        assert_eq!(output, " \t\n ");
+        assert_eq!(rest, "42");
+
+        let (rest, output) = whitespace().parse("42").unwrap();
+        assert_eq!(output, "");
+        assert_eq!(rest, "42");
+    }
+
+    #[test]
+    fn test_trim_method() {
+        let (rest, output) = uint().trim().parse("  42 \n ms").unwrap();
+        assert_eq!(output, 42);
+        assert_eq!(rest, "ms");
+
+        let (rest, output) = uint().trim().parse("42").unwrap();
+        assert_eq!(output, 42);
+        assert_eq!(rest, "");
+
+        let err = literal("GET").trim().parse("   ").unwrap_err();
+        assert_eq!(err.to_string(), "expected literal `GET` not found in input");
+    }
+
+    #[test]
+    fn test_parse_trimmed() {
+        assert_eq!(parse_trimmed(uint(), " 42\n").unwrap(), 42);
+        assert_eq!(parse_trimmed(uint(), "42").unwrap(), 42);
+        assert!(parse_trimmed(uint(), "42 ms").is_err());
+        assert!(parse_trimmed(uint(), " \n").is_err());
+    }
+
     #[test]
     fn test_end_method() {
         let input = "abc";
@@ -198,6 +1007,394 @@ mod test {
         assert_eq!(output, Duration::from_millis(500));
     }
 
+    #[test]
+    fn test_map_with_span_method() {
+        let input = "500ms";
+
+        let (rest, output) = uint()
+            .map_with_span(|int, span| (int, span))
+            .parse(input)
+            .unwrap();
+
+        assert_eq!(output, (500, 0..3));
+        assert_eq!(rest, "ms");
+
+        let (_, output) = uint()
+            .then("ms".map_with_span(|_, span| span))
+            .map_with_span(|(_, unit), total| (unit, total))
+            .parse(input)
+            .unwrap();
+
+        assert_eq!(output, (0..2, 0..5));
+    }
+
+    #[test]
+    fn test_filter_map_method() {
+        let half_of_even = || uint().filter_map(|int| (int % 2 == 0).then_some(int / 2));
+
+        let (rest, output) = half_of_even().parse("42ms").unwrap();
+        assert_eq!(output, 21);
+        assert_eq!(rest, "ms");
+
+        assert!(half_of_even().parse("7ms").is_err());
+        assert!(half_of_even().parse("ms").is_err());
+    }
+
+    #[test]
+    fn test_or_method() {
+        let input = "500ms";
+        let (rest, output) = float()
+            .or(uint().map(|int| int as f64))
+            .parse(input)
+            .unwrap();
+
+        assert_eq!(output, 500.0);
+        assert_eq!(rest, "ms");
+
+        let (rest, output) = literal("s").or("ms").parse(rest).unwrap();
+        assert_eq!(output, "ms");
+        assert!(end().parse(rest).is_ok());
+
+        let err = literal("s").or("ms").parse("us").unwrap_err();
+        assert_eq!(err.to_string(), "expected literal `ms` not found in input");
+    }
+
+    #[test]
+    fn test_optional_method() {
+        let input = "-42";
+
+        let (rest, output) = literal("-").optional().parse(input).unwrap();
+        assert_eq!(output, Some("-"));
+        assert_eq!(rest, "42");
+
+        let (rest, output) = literal("-").optional().parse(rest).unwrap();
+        assert_eq!(output, None);
+        assert_eq!(rest, "42");
+
+        // a parser that fails partway through must not leave anything consumed either
+        let (rest, output) = uint().then("ms").optional().parse("42s").unwrap();
+        assert_eq!(output, None);
+        assert_eq!(rest, "42s");
+    }
+
+    #[test]
+    fn test_or_else_method() {
+        let parser = uint().then("ms").or_else(|err| {
+            let missing_unit = err.to_string() == "second parser unsuccessful";
+            move |input| {
+                if missing_unit {
+                    uint().map(|int| (int, "ms")).parse(input)
+                } else {
+                    Ok((input, (0, "ms")))
+                }
+            }
+        });
+
+        let (rest, output) = parser.parse("500ms").unwrap();
+        assert_eq!(output, (500, "ms"));
+        assert_eq!(rest, "");
+
+        let (rest, output) = parser.parse("500").unwrap();
+        assert_eq!(output, (500, "ms"));
+        assert_eq!(rest, "");
+
+        let (rest, output) = parser.parse("ms").unwrap();
+        assert_eq!(output, (0, "ms"));
+        assert_eq!(rest, "ms");
+    }
+
+    #[test]
+    fn test_collect_string_method() {
+        let chars = |input: &'static str| -> ParseResult<'static, Vec<char>> {
+            let end = input.find(' ').unwrap_or(input.len());
+            Ok((&input[end..], input[..end].chars().collect()))
+        };
+
+        let (rest, output) = chars.collect_string().parse("café bar").unwrap();
+        assert_eq!(output, "café");
+        assert_eq!(rest, " bar");
+
+        let (rest, output) = numeric()
+            .then(numeric())
+            .map(|(first, second)| vec![first, second])
+            .collect_string()
+            .parse("123")
+            .unwrap();
+        assert_eq!(output, "12");
+        assert_eq!(rest, "3");
+    }
+
+    #[test]
+    fn test_repeated_at_least() {
+        let (rest, output) = numeric().repeated().at_least(2).parse("12x").unwrap();
+        assert_eq!(output, vec!["1", "2"]);
+        assert_eq!(rest, "x");
+
+        assert!(numeric().repeated().at_least(2).parse("1x").is_err());
+
+        let (rest, output) = numeric().repeated().parse("x").unwrap();
+        assert!(output.is_empty());
+        assert_eq!(rest, "x");
+    }
+
+    #[test]
+    fn test_repeated_at_most() {
+        let (rest, output) = numeric().repeated().at_most(2).parse("123").unwrap();
+        assert_eq!(output, vec!["1", "2"]);
+        assert_eq!(rest, "3");
+
+        let (rest, output) = numeric()
+            .repeated()
+            .at_least(1)
+            .at_most(3)
+            .parse("12x")
+            .unwrap();
+        assert_eq!(output, vec!["1", "2"]);
+        assert_eq!(rest, "x");
+    }
+
+    #[test]
+    fn test_repeated_exactly() {
+        let (rest, output) = numeric().repeated().exactly(3).parse("1234").unwrap();
+        assert_eq!(output, vec!["1", "2", "3"]);
+        assert_eq!(rest, "4");
+
+        assert!(numeric().repeated().exactly(3).parse("12").is_err());
+    }
+
+    #[test]
+    fn test_sep_count() {
+        let (rest, output) = sep_count(uint(), literal(",")).parse("1,2,3").unwrap();
+        assert_eq!(output, 3);
+        assert_eq!(rest, "");
+
+        let (rest, output) = sep_count(uint(), literal(",")).parse("42").unwrap();
+        assert_eq!(output, 1);
+        assert_eq!(rest, "");
+
+        let (rest, output) = sep_count(uint(), literal(",")).parse("1,2,").unwrap();
+        assert_eq!(output, 2);
+        assert_eq!(rest, ",");
+
+        assert!(sep_count(uint(), literal(",")).parse("").is_err());
+        assert!(sep_count(uint(), literal(",")).parse(",1").is_err());
+    }
+
+    #[test]
+    fn test_take_until_or_eof() {
+        let (rest, output) = take_until_or_eof(",").parse("key=value,next").unwrap();
+        assert_eq!(output, "key=value");
+        assert_eq!(rest, ",next");
+
+        let (rest, output) = take_until_or_eof(",").parse("lastvalue").unwrap();
+        assert_eq!(output, "lastvalue");
+        assert_eq!(rest, "");
+
+        let (rest, output) = take_until_or_eof(",").parse(",next").unwrap();
+        assert_eq!(output, "");
+        assert_eq!(rest, ",next");
+    }
+
+    #[test]
+    fn test_kv_map() {
+        let pairs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let (rest, output) = kv_map('=').parse("env=staging,team=core").unwrap();
+        assert_eq!(output, pairs(&[("env", "staging"), ("team", "core")]));
+        assert_eq!(rest, "");
+
+        let (rest, output) = kv_map(':')
+            .parse("Accept: application/json, X-Trace: abc")
+            .unwrap();
+        assert_eq!(
+            output,
+            pairs(&[("Accept", "application/json"), ("X-Trace", "abc")])
+        );
+        assert_eq!(rest, "");
+    }
+
+    #[test]
+    fn test_kv_map_value_contains_separator() {
+        let (_, output) = kv_map('=').parse("query=a=b").unwrap();
+        assert_eq!(output, vec![("query".to_string(), "a=b".to_string())]);
+
+        let (_, output) = kv_map(':').parse("Host: localhost:8080").unwrap();
+        assert_eq!(
+            output,
+            vec![("Host".to_string(), "localhost:8080".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_kv_map_empty_values() {
+        let (rest, output) = kv_map('=').parse("a=,b=2").unwrap();
+        assert_eq!(
+            output,
+            vec![
+                ("a".to_string(), "".to_string()),
+                ("b".to_string(), "2".to_string())
+            ]
+        );
+        assert_eq!(rest, "");
+
+        assert!(kv_map('=').parse("novalue").is_err());
+        assert!(kv_map('=').parse("=value").is_err());
+    }
+
+    #[test]
+    fn test_list_flexible() {
+        let inputs = vec!["1,2,3", "1\n2\n3\n", " 1, 2,\n 3 ", "1 2\t3"];
+
+        for input in inputs {
+            let (rest, output) = list_flexible(uint()).parse(input).unwrap();
+            assert_eq!(output, vec![1, 2, 3], "input {:?}", input);
+            assert_eq!(rest, "");
+        }
+
+        let (rest, output) = list_flexible(uint()).parse("1,2 x").unwrap();
+        assert_eq!(output, vec![1, 2]);
+        assert_eq!(rest, "x");
+
+        let (rest, output) = list_flexible(numeric()).parse("12").unwrap();
+        assert_eq!(output, vec!["1"]);
+        assert_eq!(rest, "2");
+
+        assert!(list_flexible(uint()).parse(" , \n").is_err());
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        let (rest, output) = percent_decode().parse("hello%20world&next=1").unwrap();
+        assert_eq!(output, "hello world");
+        assert_eq!(rest, "&next=1");
+
+        let (rest, output) = percent_decode().parse("caf%C3%A9").unwrap();
+        assert_eq!(output, "café");
+        assert_eq!(rest, "");
+
+        let err = percent_decode().parse("bad%G1").unwrap_err();
+        assert_eq!(err.to_string(), "malformed percent escape at offset 3");
+        assert!(percent_decode().parse("cut%2").is_err());
+        assert!(percent_decode().parse("%+1").is_err());
+        assert!(percent_decode().parse("%FF").is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        let inputs = vec![
+            "Authorization: Bearer abc123",
+            "X-Trace:abc",
+            "Host:   localhost:8080",
+            "X-Empty:",
+        ];
+        let expected_outputs = vec![
+            ("Authorization", "Bearer abc123"),
+            ("X-Trace", "abc"),
+            ("Host", "localhost:8080"),
+            ("X-Empty", ""),
+        ];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            let (rest, output) = header().parse(input).unwrap();
+            assert_eq!(output, expected);
+            assert_eq!(rest, "");
+        }
+
+        assert!(header().parse("NoColonHere").is_err());
+    }
+
+    #[test]
+    fn test_try_fold_many() {
+        let checked_sum = || {
+            try_fold_many(
+                uint().then(literal(",")).map(|(int, _)| int),
+                || 0u8,
+                |sum, int| {
+                    u8::try_from(int)
+                        .ok()
+                        .and_then(|int| sum.checked_add(int))
+                        .context("sum overflows u8")
+                },
+            )
+        };
+
+        let (rest, output) = checked_sum().parse("100,100,50,x").unwrap();
+        assert_eq!(output, 250);
+        assert_eq!(rest, "x");
+
+        let (rest, output) = checked_sum().parse("x").unwrap();
+        assert_eq!(output, 0);
+        assert_eq!(rest, "x");
+
+        let err = checked_sum().parse("100,100,100,").unwrap_err();
+        assert_eq!(err.to_string(), "fold failed at item 2 (offset 8)");
+        assert_eq!(err.root_cause().to_string(), "sum overflows u8");
+    }
+
+    #[test]
+    fn test_longest_of() {
+        let unit = || longest_of(vec![literal("m").boxed(), literal("ms").boxed()]);
+
+        let (rest, output) = unit().parse("ms").unwrap();
+        assert_eq!(output, "ms");
+        assert_eq!(rest, "");
+
+        let (rest, output) = unit().parse("m30s").unwrap();
+        assert_eq!(output, "m");
+        assert_eq!(rest, "30s");
+
+        assert!(unit().parse("s").is_err());
+    }
+
+    #[test]
+    fn test_longest_of_ties_prefer_first() {
+        let parser = longest_of(vec![
+            numeric().map(|_| "numeric").boxed(),
+            match_char_where(|c| c.is_ascii_digit())
+                .map(|_| "digit")
+                .boxed(),
+            uint().map(|_| "uint").boxed(),
+        ]);
+
+        let (_, output) = parser.parse("7").unwrap();
+        assert_eq!(output, "numeric");
+
+        let (_, output) = parser.parse("77").unwrap();
+        assert_eq!(output, "uint");
+    }
+
+    #[test]
+    fn test_dispatch_macro() {
+        #[derive(Debug, PartialEq)]
+        enum Command<'a> {
+            Ping,
+            Echo(&'a str),
+        }
+
+        fn command<'input>() -> impl Parser<'input, Command<'input>> {
+            dispatch! {
+                "ping" => end().map(|_| Command::Ping),
+                "echo " => rest().map(Command::Echo),
+            }
+        }
+
+        let (rest, output) = command().parse("ping").unwrap();
+        assert_eq!(output, Command::Ping);
+        assert_eq!(rest, "");
+
+        let (rest, output) = command().parse("echo hello world").unwrap();
+        assert_eq!(output, Command::Echo("hello world"));
+        assert_eq!(rest, "");
+
+        assert!(command().parse("ping pong").is_err());
+        assert!(command().parse("shout").is_err());
+    }
+
     #[test]
     fn test_one_of_macro() {
         let inputs = vec!["500ms", "2s", "1000ns", "1000000us"];
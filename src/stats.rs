@@ -0,0 +1,563 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const HALF_SUB_BUCKETS: usize = SUB_BUCKETS / 2;
+const BUCKETS: usize = SUB_BUCKETS + (64 - SUB_BUCKET_BITS as usize) * HALF_SUB_BUCKETS;
+
+/// Bounded-memory latency histogram for estimating percentiles over long runs.
+///
+/// Samples are bucketed log-linearly (HDR-histogram style): values below 128ns are
+/// stored exactly, and every power-of-two range above that is split into 64 buckets.
+/// Memory stays fixed at a few KB no matter how many samples are recorded, in exchange
+/// for reported percentiles being within ~1% of what exactly sorting every sample would
+/// give. The minimum and maximum are always exact.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+    total: u64,
+    min: u64,
+    max: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            total: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+
+        self.counts[bucket_index(nanos)] += 1;
+        self.total += 1;
+        self.min = self.min.min(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Estimated latency at percentile `p` (0.0..=100.0), using the nearest-rank method.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let rank = ((p.clamp(0.0, 100.0) / 100.0) * self.total as f64).ceil() as u64;
+        let rank = rank.max(1);
+
+        if rank == 1 {
+            return Some(Duration::from_nanos(self.min));
+        }
+        if rank >= self.total {
+            return Some(Duration::from_nanos(self.max));
+        }
+
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let estimate = bucket_midpoint(index).clamp(self.min, self.max);
+                return Some(Duration::from_nanos(estimate));
+            }
+        }
+
+        Some(Duration::from_nanos(self.max))
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Exact distribution of a small count sampled over time, such as the number of requests in
+/// flight. Memory grows with the largest level seen, not with the number of samples.
+#[derive(Debug, Clone, Default)]
+pub struct LevelHistogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl LevelHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, level: u64) {
+        let index = usize::try_from(level).expect("level should fit in memory");
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+
+        self.counts[index] += 1;
+        self.total += 1;
+    }
+
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        self.counts
+            .iter()
+            .position(|&count| count > 0)
+            .map(|level| level as u64)
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.counts
+            .iter()
+            .rposition(|&count| count > 0)
+            .map(|level| level as u64)
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let sum: u64 = (0..)
+            .zip(&self.counts)
+            .map(|(level, count)| level * count)
+            .sum();
+        Some(sum as f64 / self.total as f64)
+    }
+
+    /// Level at percentile `p` (0.0..=100.0), using the nearest-rank method.
+    pub fn percentile(&self, p: f64) -> Option<u64> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let rank = ((p.clamp(0.0, 100.0) / 100.0) * self.total as f64).ceil() as u64;
+        let rank = rank.max(1);
+
+        let mut seen = 0;
+        (0..)
+            .zip(&self.counts)
+            .find(|(_, &count)| {
+                seen += count;
+                seen >= rank
+            })
+            .map(|(level, _)| level)
+    }
+}
+
+/// Outcomes and latencies of a run's requests, fed by every worker.
+///
+/// By default every latency is kept, so percentiles are exact but memory grows with the number
+/// of requests. `Stats::approximate` uses a `LatencyHistogram` instead, trading that exactness
+/// for fixed memory.
+#[derive(Debug, Default)]
+pub struct Stats {
+    responses: Mutex<Responses>,
+    successes: AtomicU64,
+    failures: AtomicU64,
+    /// Failures that never got a response, so have no status
+    errors: AtomicU64,
+}
+
+/// Everything recorded per response, behind one lock.
+#[derive(Debug, Default)]
+struct Responses {
+    latencies: Latencies,
+    statuses: HashMap<u16, u64>,
+}
+
+#[derive(Debug)]
+enum Latencies {
+    Exact(Vec<Duration>),
+    Approximate(LatencyHistogram),
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Latencies::Exact(Vec::new())
+    }
+}
+
+impl Latencies {
+    fn record(&mut self, latency: Duration) {
+        match self {
+            Latencies::Exact(latencies) => latencies.push(latency),
+            Latencies::Approximate(histogram) => histogram.record(latency),
+        }
+    }
+
+    fn percentiles<const N: usize>(&self, ps: [f64; N]) -> [Option<Duration>; N] {
+        match self {
+            Latencies::Exact(latencies) => {
+                let mut sorted = latencies.clone();
+                sorted.sort_unstable();
+                ps.map(|p| percentile(&sorted, p))
+            }
+            Latencies::Approximate(histogram) => ps.map(|p| histogram.percentile(p)),
+        }
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like `new`, but keeps latencies in a `LatencyHistogram`, so memory stays fixed and
+    /// percentiles are within ~1% of exact.
+    pub fn approximate() -> Self {
+        Self {
+            responses: Mutex::new(Responses {
+                latencies: Latencies::Approximate(LatencyHistogram::new()),
+                statuses: HashMap::new(),
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Records a request that got a response with HTTP `status` after `latency`. Only 2xx
+    /// statuses count as successes.
+    pub fn record(&self, latency: Duration, status: u16) {
+        {
+            let mut responses = self.responses.lock().expect("stats lock poisoned");
+            responses.latencies.record(latency);
+            *responses.statuses.entry(status).or_default() += 1;
+        }
+
+        if (200..300).contains(&status) {
+            self.successes.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a request that failed without getting a response, so has no latency.
+    pub fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn summarize(&self) -> Summary {
+        let responses = self.responses.lock().expect("stats lock poisoned");
+        let [p50, p90, p99] = responses.latencies.percentiles([50.0, 90.0, 99.0]);
+        let mut statuses: Vec<_> = responses
+            .statuses
+            .iter()
+            .map(|(&status, &count)| (status, count))
+            .collect();
+        statuses.sort_unstable();
+        drop(responses);
+
+        let successes = self.successes.load(Ordering::Relaxed);
+        let failures = self.failures.load(Ordering::Relaxed);
+        Summary {
+            total: successes + failures,
+            successes,
+            failures,
+            statuses,
+            errors: self.errors.load(Ordering::Relaxed),
+            p50,
+            p90,
+            p99,
+        }
+    }
+}
+
+/// Serializes with the statuses as a map from status to count, and the latencies in
+/// milliseconds as "p50_ms", "p90_ms" and "p99_ms".
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "cli", derive(serde::Serialize))]
+pub struct Summary {
+    pub total: u64,
+    pub successes: u64,
+    pub failures: u64,
+    /// How many responses had each HTTP status, sorted by status
+    #[cfg_attr(feature = "cli", serde(serialize_with = "serialize_statuses"))]
+    pub statuses: Vec<(u16, u64)>,
+    /// Requests that failed without a response
+    pub errors: u64,
+    /// Latency percentiles of requests that got a response, `None` if none did
+    #[cfg_attr(
+        feature = "cli",
+        serde(rename = "p50_ms", serialize_with = "serialize_optional_millis")
+    )]
+    pub p50: Option<Duration>,
+    #[cfg_attr(
+        feature = "cli",
+        serde(rename = "p90_ms", serialize_with = "serialize_optional_millis")
+    )]
+    pub p90: Option<Duration>,
+    #[cfg_attr(
+        feature = "cli",
+        serde(rename = "p99_ms", serialize_with = "serialize_optional_millis")
+    )]
+    pub p99: Option<Duration>,
+}
+
+#[cfg(feature = "cli")]
+fn serialize_statuses<S>(statuses: &[(u16, u64)], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_map(statuses.iter().map(|(status, count)| (status, count)))
+}
+
+#[cfg(feature = "cli")]
+fn serialize_optional_millis<S>(
+    latency: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serde::Serialize::serialize(
+        &latency.map(|latency| latency.as_secs_f64() * 1000.0),
+        serializer,
+    )
+}
+
+/// Value at percentile `p` (0.0..=100.0) of already `sorted` samples, using the nearest-rank
+/// method.
+pub fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let rank = ((p.clamp(0.0, 100.0) / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+fn bucket_index(value: u64) -> usize {
+    let bits = u64::BITS - value.leading_zeros();
+    if bits <= SUB_BUCKET_BITS {
+        return value as usize;
+    }
+
+    let shift = bits - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) as usize - HALF_SUB_BUCKETS;
+
+    SUB_BUCKETS + (shift as usize - 1) * HALF_SUB_BUCKETS + sub_bucket
+}
+
+fn bucket_midpoint(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+
+    let shift = ((index - SUB_BUCKETS) / HALF_SUB_BUCKETS + 1) as u32;
+    let sub_bucket = ((index - SUB_BUCKETS) % HALF_SUB_BUCKETS + HALF_SUB_BUCKETS) as u64;
+
+    let low = sub_bucket << shift;
+    let width = 1u64 << shift;
+
+    low.saturating_add(width / 2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn exact_percentile(sorted: &[Duration], p: f64) -> Duration {
+        let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+        sorted[rank.max(1) - 1]
+    }
+
+    #[test]
+    fn test_bucket_index_round_trip() {
+        for value in [0, 1, 127, 128, 129, 1_000, 123_456_789, u64::MAX] {
+            let index = bucket_index(value);
+            assert!(index < BUCKETS);
+
+            let midpoint = bucket_midpoint(index) as f64;
+            let error = (midpoint - value as f64).abs() / (value as f64).max(1.0);
+            assert!(error <= 0.01, "value {value} estimated as {midpoint}");
+        }
+    }
+
+    #[test]
+    fn test_percentiles_match_exact_within_tolerance() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut histogram = LatencyHistogram::new();
+        let mut samples = Vec::new();
+
+        for _ in 0..100_000 {
+            // Mostly fast responses with a long tail, like a real service.
+            let micros = if rng.gen_bool(0.95) {
+                rng.gen_range(1_000..20_000)
+            } else {
+                rng.gen_range(20_000..2_000_000)
+            };
+            let latency = Duration::from_micros(micros);
+
+            histogram.record(latency);
+            samples.push(latency);
+        }
+        samples.sort();
+
+        assert_eq!(histogram.len(), 100_000);
+        for p in [50.0, 90.0, 99.0, 99.9] {
+            let exact = exact_percentile(&samples, p).as_secs_f64();
+            let estimate = histogram.percentile(p).unwrap().as_secs_f64();
+
+            let error = (estimate - exact).abs() / exact;
+            assert!(error <= 0.01, "p{p}: exact {exact}s, estimated {estimate}s");
+        }
+
+        assert_eq!(histogram.percentile(0.0), samples.first().copied());
+        assert_eq!(histogram.percentile(100.0), samples.last().copied());
+    }
+
+    #[test]
+    fn test_empty_histogram() {
+        let histogram = LatencyHistogram::default();
+
+        assert!(histogram.is_empty());
+        assert_eq!(histogram.percentile(50.0), None);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<_> = (1..=10).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&sorted, 0.0), Some(Duration::from_millis(1)));
+        assert_eq!(percentile(&sorted, 50.0), Some(Duration::from_millis(5)));
+        assert_eq!(percentile(&sorted, 55.0), Some(Duration::from_millis(6)));
+        assert_eq!(percentile(&sorted, 90.0), Some(Duration::from_millis(9)));
+        assert_eq!(percentile(&sorted, 99.0), Some(Duration::from_millis(10)));
+        assert_eq!(percentile(&sorted, 100.0), Some(Duration::from_millis(10)));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn test_stats_summarize() {
+        let stats = Stats::new();
+        assert_eq!(stats.summarize(), Summary::default());
+
+        // recorded out of order, as concurrent workers would
+        for (millis, status) in [(30, 200), (10, 201), (50, 500), (20, 200), (40, 204)] {
+            stats.record(Duration::from_millis(millis), status);
+        }
+        stats.record_failure();
+
+        assert_eq!(
+            stats.summarize(),
+            Summary {
+                total: 6,
+                successes: 4,
+                failures: 2,
+                statuses: vec![(200, 2), (201, 1), (204, 1), (500, 1)],
+                errors: 1,
+                p50: Some(Duration::from_millis(30)),
+                p90: Some(Duration::from_millis(50)),
+                p99: Some(Duration::from_millis(50)),
+            }
+        );
+    }
+
+    #[test]
+    fn test_approximate_stats_match_exact() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let (exact, approximate) = (Stats::new(), Stats::approximate());
+        for _ in 0..10_000 {
+            let latency = Duration::from_micros(rng.gen_range(500..250_000));
+            let status = if rng.gen_bool(0.9) { 200 } else { 503 };
+            exact.record(latency, status);
+            approximate.record(latency, status);
+        }
+        approximate.record_failure();
+        exact.record_failure();
+
+        let (exact, approximate) = (exact.summarize(), approximate.summarize());
+        for (exact, estimate) in [
+            (exact.p50, approximate.p50),
+            (exact.p90, approximate.p90),
+            (exact.p99, approximate.p99),
+        ] {
+            let (exact, estimate) = (
+                exact.unwrap().as_secs_f64(),
+                estimate.unwrap().as_secs_f64(),
+            );
+            assert!(
+                (estimate - exact).abs() / exact <= 0.01,
+                "exact {exact}s, estimated {estimate}s"
+            );
+        }
+
+        let counts_only = |summary: Summary| Summary {
+            p50: None,
+            p90: None,
+            p99: None,
+            ..summary
+        };
+        assert_eq!(counts_only(approximate), counts_only(exact));
+    }
+
+    #[test]
+    fn test_stats_count_statuses() {
+        let stats = Stats::new();
+        let outcomes = [
+            Some(200),
+            Some(429),
+            None,
+            Some(200),
+            Some(500),
+            Some(429),
+            Some(200),
+            None,
+        ];
+        for outcome in outcomes {
+            match outcome {
+                Some(status) => stats.record(Duration::from_millis(1), status),
+                None => stats.record_failure(),
+            }
+        }
+
+        let summary = stats.summarize();
+        assert_eq!(summary.statuses, vec![(200, 3), (429, 2), (500, 1)]);
+        assert_eq!(summary.errors, 2);
+        assert_eq!(summary.successes, 3);
+        assert_eq!(summary.failures, 5);
+    }
+
+    #[test]
+    fn test_level_histogram() {
+        let mut histogram = LevelHistogram::new();
+        assert_eq!(histogram.max(), None);
+        assert_eq!(histogram.mean(), None);
+
+        // 98 samples at 2 in flight, with one dip to 1 and one spike to 8
+        for _ in 0..98 {
+            histogram.record(2);
+        }
+        histogram.record(1);
+        histogram.record(8);
+
+        assert_eq!(histogram.len(), 100);
+        assert_eq!(histogram.min(), Some(1));
+        assert_eq!(histogram.max(), Some(8));
+        assert_eq!(histogram.mean(), Some(2.05));
+        assert_eq!(histogram.percentile(0.0), Some(1));
+        assert_eq!(histogram.percentile(50.0), Some(2));
+        assert_eq!(histogram.percentile(99.0), Some(2));
+        assert_eq!(histogram.percentile(100.0), Some(8));
+    }
+}
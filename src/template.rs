@@ -0,0 +1,261 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+use serde_json::Value;
+
+use crate::dispatch;
+use crate::parsers::{literal, take_until_or_eof, uint, Parser};
+
+/// Per-request values that placeholders are filled in from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestCtx {
+    /// Position of this request in the run, shared across all workers
+    pub counter: u64,
+    pub timestamp: SystemTime,
+}
+
+impl RequestCtx {
+    pub fn new(counter: u64) -> Self {
+        Self {
+            counter,
+            timestamp: SystemTime::now(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Uuid,
+    /// Milliseconds since the Unix epoch
+    Timestamp,
+    Counter,
+    /// Inclusive on both ends
+    RandomInt(u64, u64),
+}
+
+impl Token {
+    fn render<R>(self, ctx: &RequestCtx, rng: &mut R) -> Option<String>
+    where
+        R: Rng + ?Sized,
+    {
+        match self {
+            Token::Uuid => Some(uuid_v4(rng)),
+            Token::Timestamp => {
+                let since_epoch = ctx.timestamp.duration_since(UNIX_EPOCH).ok()?;
+                Some(since_epoch.as_millis().to_string())
+            }
+            Token::Counter => Some(ctx.counter.to_string()),
+            Token::RandomInt(min, max) if min <= max => Some(rng.gen_range(min..=max).to_string()),
+            Token::RandomInt(..) => None,
+        }
+    }
+}
+
+fn token<'input>() -> impl Parser<'input, Token> {
+    literal("{{")
+        .then(dispatch! {
+            "uuid" => literal("}}").map(|_| Token::Uuid),
+            "timestamp" => literal("}}").map(|_| Token::Timestamp),
+            "counter" => literal("}}").map(|_| Token::Counter),
+            "random_int:" => uint()
+                .then("-")
+                .then(uint())
+                .then("}}")
+                .map(|(((min, _), max), _)| Token::RandomInt(min, max)),
+        })
+        .map(|(_, token)| token)
+}
+
+fn uuid_v4<R>(rng: &mut R) -> String
+where
+    R: Rng + ?Sized,
+{
+    let mut bytes: [u8; 16] = rng.gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn render_str<R>(template: &str, ctx: &RequestCtx, rng: &mut R) -> String
+where
+    R: Rng + ?Sized,
+{
+    let mut rendered = String::with_capacity(template.len());
+    let mut remaining = template;
+
+    while !remaining.is_empty() {
+        let (rest, text) = take_until_or_eof("{{")
+            .parse(remaining)
+            .expect("take_until_or_eof never fails");
+        rendered.push_str(text);
+
+        if rest.is_empty() {
+            break;
+        }
+
+        match token().parse(rest) {
+            Ok((after, token)) => {
+                // tokens that can't be filled in, like `random_int:9-1`, are kept as written
+                let original = &rest[..rest.len() - after.len()];
+                rendered.push_str(token.render(ctx, rng).as_deref().unwrap_or(original));
+                remaining = after;
+            }
+            // not one of ours, so keep the brace and look for a placeholder starting after it
+            Err(_) => {
+                rendered.push('{');
+                remaining = &rest[1..];
+            }
+        }
+    }
+
+    rendered
+}
+
+/// Fills `{{uuid}}`, `{{timestamp}}`, `{{counter}}` and `{{random_int:MIN-MAX}}` placeholders in
+/// every string value of `template`, however deeply nested. Object keys and unknown placeholders
+/// are left untouched.
+pub fn render(template: &Value, ctx: &RequestCtx) -> Value {
+    render_with(template, ctx, &mut rand::thread_rng())
+}
+
+fn render_with<R>(template: &Value, ctx: &RequestCtx, rng: &mut R) -> Value
+where
+    R: Rng + ?Sized,
+{
+    match template {
+        Value::String(template) => Value::String(render_str(template, ctx, rng)),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| render_with(item, ctx, rng))
+                .collect(),
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), render_with(value, ctx, rng)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Whether `template` has anything for `render` to fill in, so plain payloads can skip it.
+pub fn has_placeholders(template: &Value) -> bool {
+    match template {
+        Value::String(template) => template.contains("{{"),
+        Value::Array(items) => items.iter().any(has_placeholders),
+        Value::Object(fields) => fields.values().any(has_placeholders),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn ctx() -> RequestCtx {
+        RequestCtx {
+            counter: 7,
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+        }
+    }
+
+    #[test]
+    fn test_token() {
+        let inputs = vec![
+            "{{uuid}}",
+            "{{timestamp}}",
+            "{{counter}}",
+            "{{random_int:1-100}}",
+        ];
+        let expected_outputs = vec![
+            Token::Uuid,
+            Token::Timestamp,
+            Token::Counter,
+            Token::RandomInt(1, 100),
+        ];
+
+        for (input, expected) in inputs.into_iter().zip(expected_outputs) {
+            let (rest, output) = token().parse(input).unwrap();
+            assert_eq!(output, expected);
+            assert_eq!(rest, "");
+        }
+
+        for input in ["{{name}}", "{{counter", "{{random_int:1}}", "{counter}"] {
+            assert!(token().parse(input).is_err(), "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_render_nested() {
+        let template = json!({
+            "id": "req-{{counter}}",
+            "sent_at": "{{timestamp}}",
+            "tags": ["{{counter}}", 3, null, {"deep": "{{counter}}/{{counter}}"}],
+            "{{counter}}": true,
+        });
+
+        let rendered = render(&template, &ctx());
+
+        assert_eq!(
+            rendered,
+            json!({
+                "id": "req-7",
+                "sent_at": "1700000000123",
+                "tags": ["7", 3, null, {"deep": "7/7"}],
+                "{{counter}}": true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_render_leaves_unknown_tokens() {
+        let template = json!("{{name}} {{counter {{random_int:9-1}} {{{counter}}}");
+
+        assert_eq!(
+            render(&template, &ctx()),
+            json!("{{name}} {{counter {{random_int:9-1}} {7}")
+        );
+    }
+
+    #[test]
+    fn test_render_random_values() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let template = json!(["{{uuid}}", "{{random_int:1-100}}"]);
+
+        let mut uuids = std::collections::HashSet::new();
+        for _ in 0..1_000 {
+            let rendered = render_with(&template, &ctx(), &mut rng);
+            let [uuid, int] = [&rendered[0], &rendered[1]].map(|v| v.as_str().unwrap());
+
+            assert_eq!(uuid.len(), 36);
+            assert_eq!(&uuid[14..15], "4");
+            assert!("89ab".contains(&uuid[19..20]), "uuid {uuid}");
+            uuids.insert(uuid.to_string());
+
+            let int: u64 = int.parse().unwrap();
+            assert!((1..=100).contains(&int), "int {int}");
+        }
+        assert_eq!(uuids.len(), 1_000);
+    }
+
+    #[test]
+    fn test_has_placeholders() {
+        assert!(has_placeholders(&json!({"a": ["{{counter}}"]})));
+        assert!(!has_placeholders(&json!({"a": ["plain", 1], "{{key}}": 2})));
+    }
+}
@@ -2,26 +2,84 @@ use std::{
     future::poll_fn,
     pin::Pin,
     task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use futures::{ready, Future, Stream};
-use tokio::time::{Duration, Instant, Sleep};
+use rand::Rng;
+use tokio::time::{Duration, Instant, MissedTickBehavior, Sleep};
+
+/// How a jittered delay is spread around its base duration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JitterDistribution {
+    /// Multiplier drawn uniformly from `[1 - factor, 1 + factor)`, i.e. base ± factor
+    Uniform { factor: f64 },
+    /// Normally distributed around the base, with `std_dev` as a fraction of it
+    Normal { std_dev: f64 },
+    /// Exponentially distributed with the base as its mean, like Poisson arrivals
+    Exponential,
+}
+
+impl JitterDistribution {
+    pub fn sample<R>(&self, base: Duration, rng: &mut R) -> Duration
+    where
+        R: Rng + ?Sized,
+    {
+        let multiplier = match *self {
+            JitterDistribution::Uniform { factor } => 1.0 + (rng.gen::<f64>() * 2.0 - 1.0) * factor,
+            JitterDistribution::Normal { std_dev } => 1.0 + standard_normal(rng) * std_dev,
+            JitterDistribution::Exponential => -(1.0 - rng.gen::<f64>()).ln(),
+        };
+
+        base.mul_f64(multiplier.max(0.0))
+    }
+}
 
 pub struct JitterInterval {
     sleep: Pin<Box<Sleep>>,
     base_duration: Duration,
-    factor: f64,
+    distribution: JitterDistribution,
+    missed_tick_behavior: MissedTickBehavior,
+    max_duration: Option<Duration>,
 }
 
 impl JitterInterval {
     pub fn new(base_duration: Duration, factor: f64) -> Self {
-        let duration = jitter(base_duration, factor);
+        Self::with_distribution(base_duration, JitterDistribution::Uniform { factor })
+    }
+
+    pub fn with_distribution(base_duration: Duration, distribution: JitterDistribution) -> Self {
+        let duration = distribution.sample(base_duration, &mut rand::thread_rng());
         let sleep = Box::pin(tokio::time::sleep(duration));
 
         Self {
             sleep,
             base_duration,
-            factor,
+            distribution,
+            missed_tick_behavior: MissedTickBehavior::Delay,
+            max_duration: None,
+        }
+    }
+
+    /// Caps every jittered delay at `max`, so a large jitter factor can't stall ticks.
+    pub fn with_max(mut self, max: Duration) -> Self {
+        let latest = Instant::now() + max;
+        if self.sleep.deadline() > latest {
+            self.sleep.as_mut().reset(latest);
+        }
+
+        Self {
+            max_duration: Some(max),
+            ..self
+        }
+    }
+
+    /// How to catch up after a tick was polled late, with the same meaning as for tokio's
+    /// `Interval` but over the jittered schedule. Defaults to `Delay`.
+    pub fn with_missed_tick_behavior(self, missed_tick_behavior: MissedTickBehavior) -> Self {
+        Self {
+            missed_tick_behavior,
+            ..self
         }
     }
 
@@ -34,10 +92,32 @@ impl JitterInterval {
     pub(crate) fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
         ready!(Pin::new(&mut self.sleep).poll(cx));
 
-        let next_duration = jitter(self.base_duration, self.factor);
+        let next_duration = self
+            .distribution
+            .sample(self.base_duration, &mut rand::thread_rng());
+        let next_duration = self
+            .max_duration
+            .map_or(next_duration, |max| next_duration.min(max));
+        let deadline = self.sleep.deadline();
         let now = Instant::now();
 
-        self.sleep.as_mut().reset(now + next_duration);
+        let next = match self.missed_tick_behavior {
+            MissedTickBehavior::Delay => now + next_duration,
+            // keep to the schedule, firing straight away for every tick that was missed
+            MissedTickBehavior::Burst => deadline + next_duration,
+            MissedTickBehavior::Skip => {
+                let next = deadline + next_duration;
+                if next > now || self.base_duration.is_zero() {
+                    next
+                } else {
+                    let missed = (now - next).as_nanos() / self.base_duration.as_nanos() + 1;
+                    let missed = u32::try_from(missed).unwrap_or(u32::MAX);
+                    next + self.base_duration.saturating_mul(missed)
+                }
+            }
+        };
+
+        self.sleep.as_mut().reset(next);
 
         Poll::Ready(now)
     }
@@ -55,6 +135,235 @@ impl Stream for JitterInterval {
     }
 }
 
-fn jitter(duration: Duration, factor: f64) -> Duration {
-    duration.mul_f64(rand::random::<f64>() + factor)
+/// Ticks on wall-clock multiples of `period` since the Unix epoch, e.g. at the top of every
+/// second for a one second period, rather than at offsets from when it was created.
+///
+/// The wall clock is only read once, to find the first boundary; after that ticks follow the
+/// monotonic clock, so adjusting the system time mid-run doesn't bunch or drop ticks. A tick
+/// that is polled late skips ahead to the next boundary instead of bursting to catch up.
+pub struct AlignedInterval {
+    sleep: Pin<Box<Sleep>>,
+    period: Duration,
+}
+
+impl AlignedInterval {
+    /// Panics if `period` is zero.
+    pub fn new(period: Duration) -> Self {
+        Self::aligned_to(period, SystemTime::now())
+    }
+
+    fn aligned_to(period: Duration, wall_clock: SystemTime) -> Self {
+        assert!(!period.is_zero(), "`period` must be non-zero");
+
+        let since_epoch = wall_clock.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let into_period = since_epoch.as_nanos() % period.as_nanos();
+        let until_boundary = match u64::try_from(into_period).expect("less than `period`") {
+            0 => Duration::ZERO,
+            into_period => period - Duration::from_nanos(into_period),
+        };
+
+        Self {
+            sleep: Box::pin(tokio::time::sleep(until_boundary)),
+            period,
+        }
+    }
+
+    pub async fn tick(&mut self) -> Instant {
+        let instant = poll_fn(|cx| self.poll_tick(cx));
+
+        instant.await
+    }
+
+    pub(crate) fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        ready!(Pin::new(&mut self.sleep).poll(cx));
+
+        // the deadline is on a boundary, so is every whole number of periods after it
+        let deadline = self.sleep.deadline();
+        let now = Instant::now();
+        let missed = (now - deadline).as_nanos() / self.period.as_nanos();
+        let periods = u32::try_from(missed + 1).unwrap_or(u32::MAX);
+
+        self.sleep
+            .as_mut()
+            .reset(deadline + self.period.saturating_mul(periods));
+
+        Poll::Ready(now)
+    }
+}
+
+impl Stream for AlignedInterval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.as_mut().poll_tick(cx).map(Some)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
+
+// Box-Muller transform, to avoid pulling in `rand_distr` for a single distribution
+fn standard_normal<R>(rng: &mut R) -> f64
+where
+    R: Rng + ?Sized,
+{
+    let u1 = 1.0 - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+
+    (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::FutureExt;
+    use pretty_assertions::assert_eq;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const BASE: Duration = Duration::from_millis(100);
+    const SAMPLES: usize = 100_000;
+
+    fn sample_multipliers(distribution: JitterDistribution) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(42);
+
+        (0..SAMPLES)
+            .map(|_| distribution.sample(BASE, &mut rng).as_secs_f64() / BASE.as_secs_f64())
+            .collect()
+    }
+
+    fn mean(values: &[f64]) -> f64 {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+
+    fn std_dev(values: &[f64]) -> f64 {
+        let mean = mean(values);
+        (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt()
+    }
+
+    /// Ticks once on schedule, stalls for 3.5 periods, then counts how many ticks are ready
+    /// straight away and when the one after them is due.
+    async fn ticks_after_stall(behavior: MissedTickBehavior) -> (usize, Duration) {
+        let start = Instant::now();
+        let mut interval = JitterInterval::new(BASE, 0.0).with_missed_tick_behavior(behavior);
+
+        interval.tick().await;
+        tokio::time::advance(BASE * 7 / 2).await;
+
+        let mut ready = 0;
+        while interval.tick().now_or_never().is_some() {
+            ready += 1;
+        }
+        interval.tick().await;
+
+        (ready, Instant::now() - start)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_missed_tick_behavior() {
+        // the stall ends at 450ms, after ticks due at 200, 300 and 400ms were missed
+        assert_eq!(
+            ticks_after_stall(MissedTickBehavior::Burst).await,
+            (3, Duration::from_millis(500))
+        );
+        assert_eq!(
+            ticks_after_stall(MissedTickBehavior::Delay).await,
+            (1, Duration::from_millis(550))
+        );
+        assert_eq!(
+            ticks_after_stall(MissedTickBehavior::Skip).await,
+            (1, Duration::from_millis(500))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_duration_caps_ticks() {
+        let max = Duration::from_millis(150);
+        let mut interval =
+            JitterInterval::with_distribution(BASE, JitterDistribution::Exponential).with_max(max);
+
+        let mut last = Instant::now();
+        let mut capped = 0;
+        for _ in 0..10_000 {
+            interval.tick().await;
+            let gap = last.elapsed();
+            assert!(gap <= max, "gap {:?}", gap);
+            capped += usize::from(gap == max);
+            last = Instant::now();
+        }
+
+        // with an exponential mean of 100ms, about e^-1.5 (22%) of draws go over the cap
+        assert!((1_800..2_700).contains(&capped), "capped {}", capped);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_aligned_interval_ticks_on_boundaries() {
+        let start = Instant::now();
+        // created 250ms into a wall-clock second
+        let wall_clock = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        let mut interval = AlignedInterval::aligned_to(Duration::from_secs(1), wall_clock);
+
+        let mut offsets = Vec::new();
+        for _ in 0..3 {
+            interval.tick().await;
+            offsets.push(start.elapsed());
+        }
+
+        // stalling past the tick due at 3.75s fires it late, then picks up at the next boundary
+        tokio::time::advance(Duration::from_millis(1_500)).await;
+        for _ in 0..2 {
+            interval.tick().await;
+            offsets.push(start.elapsed());
+        }
+
+        assert_eq!(
+            offsets,
+            [750, 1_750, 2_750, 4_250, 4_750].map(Duration::from_millis)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_aligned_interval_on_a_boundary_ticks_immediately() {
+        let start = Instant::now();
+        let wall_clock = UNIX_EPOCH + Duration::from_secs(1_700_000_040);
+        let mut interval = AlignedInterval::aligned_to(Duration::from_secs(60), wall_clock);
+
+        interval.tick().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        interval.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_uniform_distribution() {
+        let multipliers = sample_multipliers(JitterDistribution::Uniform { factor: 0.1 });
+
+        // a hair of slack for durations truncating to whole nanoseconds
+        assert!(multipliers.iter().all(|m| (0.9 - 1e-9..1.1).contains(m)));
+        assert!((mean(&multipliers) - 1.0).abs() < 0.001);
+
+        // centered on the base, and actually spread across the whole window
+        let min = multipliers.iter().copied().fold(f64::MAX, f64::min);
+        let max = multipliers.iter().copied().fold(f64::MIN, f64::max);
+        assert!(min < 0.901 && max > 1.099, "min {min}, max {max}");
+    }
+
+    #[test]
+    fn test_normal_distribution() {
+        let multipliers = sample_multipliers(JitterDistribution::Normal { std_dev: 0.1 });
+
+        assert!(multipliers.iter().all(|m| *m >= 0.0));
+        assert!((mean(&multipliers) - 1.0).abs() < 0.01);
+        assert!((std_dev(&multipliers) - 0.1).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_exponential_distribution() {
+        let multipliers = sample_multipliers(JitterDistribution::Exponential);
+
+        assert!(multipliers.iter().all(|m| *m >= 0.0));
+        // an exponential distribution's standard deviation equals its mean
+        assert!((mean(&multipliers) - 1.0).abs() < 0.02);
+        assert!((std_dev(&multipliers) - 1.0).abs() < 0.02);
+    }
 }
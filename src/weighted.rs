@@ -0,0 +1,124 @@
+use anyhow::Context;
+use rand::Rng;
+
+use crate::parsers::{literal, separated_list, uint, ParseResult, Parser};
+
+/// Picks one of several values with probability proportional to its weight.
+#[derive(Debug, Clone)]
+pub struct WeightedChoice<T> {
+    choices: Vec<T>,
+    cumulative: Vec<u64>,
+}
+
+impl<T> WeightedChoice<T> {
+    pub fn new(weighted: Vec<(u64, T)>) -> anyhow::Result<Self> {
+        let mut choices = Vec::with_capacity(weighted.len());
+        let mut cumulative = Vec::with_capacity(weighted.len());
+        let mut total: u64 = 0;
+
+        for (weight, choice) in weighted {
+            total = total
+                .checked_add(weight)
+                .context("sum of weights overflows u64")?;
+            choices.push(choice);
+            cumulative.push(total);
+        }
+
+        anyhow::ensure!(total > 0, "at least one weight must be greater than zero");
+
+        Ok(Self {
+            choices,
+            cumulative,
+        })
+    }
+
+    pub fn choose<R>(&self, rng: &mut R) -> &T
+    where
+        R: Rng + ?Sized,
+    {
+        let total = *self
+            .cumulative
+            .last()
+            .expect("constructor rejects empty choices");
+        let point = rng.gen_range(0..total);
+        let index = self.cumulative.partition_point(|&bound| bound <= point);
+
+        &self.choices[index]
+    }
+}
+
+/// Parses a comma separated list of `weight=value` entries, e.g. `3=GET,1=POST`.
+pub fn weighted<'input, P, T>(value: P) -> impl Parser<'input, Vec<(u64, T)>>
+where
+    P: Parser<'input, T>,
+{
+    move |input| {
+        let entry = |input| -> ParseResult<'input, (u64, T)> {
+            let (rest, (weight, _)) = uint().then(literal("=")).parse(input)?;
+            let (rest, value) = value.parse(rest)?;
+            Ok((rest, (weight, value)))
+        };
+
+        separated_list(entry, literal(","))
+            .parse(input)
+            .context("expected a `weight=value` entry")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::one_of;
+    use pretty_assertions::assert_eq;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_weighted_spec_parser() {
+        let (rest, output) = weighted(one_of!("GET", "POST", "PUT"))
+            .parse("3=GET,1=POST,0=PUT")
+            .unwrap();
+
+        assert_eq!(output, vec![(3, "GET"), (1, "POST"), (0, "PUT")]);
+        assert_eq!(rest, "");
+
+        let (rest, output) = weighted(uint()).parse("1=200,").unwrap();
+        assert_eq!(output, vec![(1, 200)]);
+        assert_eq!(rest, ",");
+
+        assert!(weighted(uint()).parse("=200").is_err());
+        assert!(weighted(uint()).parse("1:200").is_err());
+    }
+
+    #[test]
+    fn test_weighted_choice_rejects_zero_total() {
+        assert!(WeightedChoice::<&str>::new(vec![]).is_err());
+        assert!(WeightedChoice::new(vec![(0, "GET"), (0, "POST")]).is_err());
+    }
+
+    #[test]
+    fn test_weighted_choice_converges_to_weights() {
+        let (_, spec) = weighted(one_of!("GET", "POST", "PUT"))
+            .parse("6=GET,3=POST,1=PUT")
+            .unwrap();
+        let choice = WeightedChoice::new(spec).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut counts = [0; 3];
+        for _ in 0..100_000 {
+            match *choice.choose(&mut rng) {
+                "GET" => counts[0] += 1,
+                "POST" => counts[1] += 1,
+                "PUT" => counts[2] += 1,
+                other => unreachable!("unexpected choice {other}"),
+            }
+        }
+
+        for (count, expected) in counts.into_iter().zip([0.6, 0.3, 0.1]) {
+            let share = count as f64 / 100_000.0;
+            assert!(
+                (share - expected).abs() < 0.01,
+                "share {share}, expected {expected}"
+            );
+        }
+    }
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "cli")]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use barrage::cli::{run_with_cancel, Args, ExitReason};
+use barrage::middleware::MiddlewareChain;
+use clap::Parser;
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn test_embedder_cancels_run_with_its_own_middleware() {
+    let args = Args::try_parse_from([
+        "barrage",
+        "http://127.0.0.1:1",
+        "--data",
+        "hello",
+        "--every",
+        "10ms",
+    ])
+    .unwrap();
+
+    let applied = Arc::new(AtomicU64::new(0));
+    let counted = Arc::clone(&applied);
+    let middleware = MiddlewareChain::new().with(move |request: reqwest::Request| {
+        counted.fetch_add(1, Ordering::Relaxed);
+        request
+    });
+
+    let cancel = CancellationToken::new();
+    let cancel_later = cancel.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        cancel_later.cancel();
+    });
+
+    // without a count this would run forever, so returning at all means it was cancelled
+    let run = tokio::time::timeout(
+        Duration::from_secs(5),
+        run_with_cancel(args, middleware, cancel),
+    )
+    .await
+    .expect("run should stop once cancelled");
+
+    assert!(run.dispatched > 0);
+    assert!(applied.load(Ordering::Relaxed) > 0);
+    assert_eq!(run.exit_reason, ExitReason::TargetUnreachable);
+}
@@ -0,0 +1,21 @@
+use std::{path::Path, process::Command};
+
+#[test]
+fn test_parsers_build_without_cli_feature() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    let output = Command::new(env!("CARGO"))
+        .args(["check", "--lib", "--no-default-features", "--quiet"])
+        .arg("--manifest-path")
+        .arg(manifest_dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(manifest_dir.join("target").join("no-default-features"))
+        .output()
+        .expect("cargo should be runnable from tests");
+
+    assert!(
+        output.status.success(),
+        "library failed to build without the `cli` feature:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}